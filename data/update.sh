@@ -0,0 +1,14 @@
+#!/bin/sh
+# Replaces the bundled rom database with the community CHIP-8 database's
+# programs.json and its licence.
+set -eu
+
+BASE=https://raw.githubusercontent.com/chip-8/chip-8-database/master
+DIR=$(dirname "$0")
+
+curl -fsSL "$BASE/database/programs.json" -o "$DIR/roms.json.new"
+curl -fsSL "$BASE/LICENSE" -o "$DIR/LICENSE"
+mv "$DIR/roms.json.new" "$DIR/roms.json"
+
+# make sure the new database still parses
+cargo test --no-default-features --lib database
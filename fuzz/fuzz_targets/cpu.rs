@@ -0,0 +1,35 @@
+#![no_main]
+
+use chip8::{Cpu, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+// the first byte picks the quirks and the next two the keys held; the rest
+// is the program
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let flags = data[0];
+    let quirks = Quirks {
+        vf_reset: flags & 0x01 != 0,
+        increment_i: flags & 0x02 != 0,
+        shift_vx: flags & 0x04 != 0,
+        jump_vx: flags & 0x08 != 0,
+        clip_sprites: flags & 0x10 != 0,
+    };
+    let held = u16::from_be_bytes([data[1], data[2]]);
+
+    let mut cpu = Cpu::init(quirks);
+    if cpu.load(&data[3..]).is_err() {
+        return;
+    }
+    cpu.reseed(0);
+    for key in 0..16 {
+        cpu.keys[key] = held & 1 << key != 0;
+    }
+    for _ in 0..100 {
+        if cpu.run_frame(10).is_err() || cpu.exited() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use chip8::instruction::Instruction;
+use libfuzzer_sys::fuzz_target;
+
+// every word either decodes to an instruction that encodes back to it or is
+// rejected, without panicking
+fuzz_target!(|words: Vec<u16>| {
+    for word in words {
+        if let Ok(inst) = Instruction::parse(word) {
+            assert_eq!(inst.encode(), word, "{}", inst);
+        }
+    }
+});
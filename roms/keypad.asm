@@ -0,0 +1,12 @@
+; Shows the hex digit of each key pressed in the middle of the screen.
+; Assemble with `chip8-asm roms/keypad.asm -o roms/keypad.ch8`.
+X = 30
+Y = 13
+start:
+    LD V0, K
+    CLS
+    LD F, V0
+    LD V1, X
+    LD V2, Y
+    DRW V1, V2, 5
+    JP start
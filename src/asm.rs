@@ -0,0 +1,503 @@
+use crate::cpu::{MEMORY_SIZE, START_ADDRESS};
+use crate::instruction::Instruction;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An error in assembly source, with the file and line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A source line, after includes are expanded.
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message,
+        }
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(String),
+}
+
+enum Item {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+/// An operand of an instruction, such as `V3`, `[I]` or `0x200`.
+#[derive(Debug, PartialEq)]
+enum Operand {
+    V(u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Hf,
+    R,
+    Long(u32),
+    Value(u32),
+}
+
+/// Assembles `source` into a ROM to be loaded at [`START_ADDRESS`].
+///
+/// Included files are read relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand(
+        source,
+        "<source>",
+        Path::new("."),
+        &mut Vec::new(),
+        &mut lines,
+    )?;
+
+    Assembler::default().assemble(&lines)
+}
+
+/// Assembles the file at `path`, reading included files relative to the
+/// file that includes them.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let source = fs::read_to_string(path).map_err(|error| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        message: error.to_string(),
+    })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut including = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+    let mut lines = Vec::new();
+    expand(
+        &source,
+        &path.display().to_string(),
+        dir,
+        &mut including,
+        &mut lines,
+    )?;
+
+    Assembler::default().assemble(&lines)
+}
+
+/// Splits `source` into lines, replacing each `include "file"` with the
+/// lines of that file.
+///
+/// `including` holds the files currently being expanded, to catch a file
+/// that includes itself.
+fn expand(
+    source: &str,
+    file: &str,
+    dir: &Path,
+    including: &mut Vec<PathBuf>,
+    lines: &mut Vec<Line>,
+) -> Result<(), AsmError> {
+    for (index, text) in source.lines().enumerate() {
+        let line = Line {
+            file: file.to_string(),
+            number: index + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+
+        let (word, rest) = split_word(&line.text);
+        if !word.eq_ignore_ascii_case("include") {
+            lines.push(line);
+            continue;
+        }
+
+        let path = dir.join(rest.trim_matches('"'));
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if including.contains(&canonical) {
+            return Err(line.error(format!("{} includes itself", path.display())));
+        }
+        let source = fs::read_to_string(&path)
+            .map_err(|error| line.error(format!("{}: {}", path.display(), error)))?;
+
+        including.push(canonical);
+        let dir = path.parent().unwrap_or(dir);
+        expand(&source, &path.display().to_string(), dir, including, lines)?;
+        including.pop();
+    }
+
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find(';') {
+        Some(comment) => &text[..comment],
+        None => text,
+    }
+}
+
+/// Splits off the first whitespace separated word of `text`.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(split) => (&text[..split], text[split..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Checks that `value` fits in a field of `bits` bits.
+fn fit(value: u32, bits: u32) -> Result<u16, String> {
+    if value < 1 << bits {
+        Ok(value as u16)
+    } else {
+        Err(format!("{:#x} does not fit in {} bits", value, bits))
+    }
+}
+
+/// A two pass assembler: the first pass lays out addresses and collects
+/// labels and constants, the second encodes instructions and data.
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn assemble(mut self, lines: &[Line]) -> Result<Vec<u8>, AsmError> {
+        let mut items = Vec::new();
+        let mut addr = START_ADDRESS as usize;
+
+        for line in lines {
+            let mut text = line.text.as_str();
+            if let Some((name, rest)) = text.split_once(':') {
+                if is_identifier(name.trim()) {
+                    self.define(name.trim(), Symbol::Label(addr as u16))
+                        .map_err(|message| line.error(message))?;
+                    text = rest.trim();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+            if let Some((name, value)) = text.split_once('=') {
+                self.define(name.trim(), Symbol::Constant(value.trim().to_string()))
+                    .map_err(|message| line.error(message))?;
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            let args: Vec<String> = match rest {
+                "" => Vec::new(),
+                rest => rest.split(',').map(|arg| arg.trim().to_string()).collect(),
+            };
+            let (item, size) = match word.to_ascii_lowercase().as_str() {
+                "db" => (Item::Bytes(args.clone()), args.len()),
+                "dw" => (Item::Words(args.clone()), args.len() * 2),
+                _ => {
+                    let long = args
+                        .iter()
+                        .any(|arg| split_word(arg).0.eq_ignore_ascii_case("long"));
+                    let size = if long { 4 } else { 2 };
+                    (Item::Instruction(word.to_ascii_uppercase(), args), size)
+                }
+            };
+
+            addr += size;
+            if addr > MEMORY_SIZE {
+                return Err(line.error("program does not fit in memory".to_string()));
+            }
+            items.push((line, item));
+        }
+
+        let mut rom = Vec::new();
+        for (line, item) in items {
+            self.emit(&item, &mut rom)
+                .map_err(|message| line.error(message))?;
+        }
+
+        Ok(rom)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("invalid name {}", name));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a number, label or constant.
+    fn value(&self, text: &str) -> Result<u32, String> {
+        let mut text = text;
+        // constants followed so far, to catch cycles
+        let mut seen = HashSet::new();
+        loop {
+            if let Some(value) = parse_number(text) {
+                return Ok(value);
+            }
+            match self.symbols.get(text) {
+                Some(Symbol::Label(addr)) => return Ok(*addr as u32),
+                Some(Symbol::Constant(_)) if !seen.insert(text) => {
+                    return Err(format!("constant {} refers to itself", text))
+                }
+                Some(Symbol::Constant(value)) => text = value,
+                None => return Err(format!("unknown value {}", text)),
+            }
+        }
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "B" => Operand::B,
+            "HF" => Operand::Hf,
+            "R" => Operand::R,
+            _ => match split_word(text) {
+                (long, addr) if long.eq_ignore_ascii_case("long") => {
+                    Operand::Long(self.value(addr)?)
+                }
+                _ if upper.len() == 2 && upper.starts_with('V') => {
+                    match u16::from_str_radix(&upper[1..], 16) {
+                        Ok(vx) => Operand::V(vx),
+                        Err(_) => Operand::Value(self.value(text)?),
+                    }
+                }
+                _ => Operand::Value(self.value(text)?),
+            },
+        };
+
+        Ok(operand)
+    }
+
+    fn emit(&self, item: &Item, rom: &mut Vec<u8>) -> Result<(), String> {
+        match item {
+            Item::Bytes(values) => {
+                for value in values {
+                    rom.push(fit(self.value(value)?, 8)? as u8);
+                }
+            }
+            Item::Words(values) => {
+                for value in values {
+                    rom.extend_from_slice(&fit(self.value(value)?, 16)?.to_be_bytes());
+                }
+            }
+            Item::Instruction(mnemonic, args) => {
+                let operands = args
+                    .iter()
+                    .map(|arg| self.operand(arg))
+                    .collect::<Result<Vec<Operand>, String>>()?;
+                let inst = instruction(mnemonic, &operands)?;
+
+                rom.extend_from_slice(&inst.encode().to_be_bytes());
+                if let Instruction::LD_I_LONG(addr) = inst {
+                    rom.extend_from_slice(&addr.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the instruction for `mnemonic` and its operands.
+fn instruction(mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+    use Operand::*;
+
+    let byte = |value: u32| fit(value, 8).map(|value| value as u8);
+    let nibble = |value: u32| fit(value, 4).map(|value| value as u8);
+    let addr = |value: u32| fit(value, 12);
+
+    let inst = match (mnemonic, operands) {
+        ("CLS", []) => Instruction::CLS,
+        ("RET", []) => Instruction::RET,
+        ("SYS", [Value(nnn)]) => Instruction::SYS(addr(*nnn)?),
+        ("JP", [Value(nnn)]) => Instruction::JP_ADDR(addr(*nnn)?),
+        ("JP", [V(0), Value(nnn)]) => Instruction::JP_V0(addr(*nnn)?),
+        ("CALL", [Value(nnn)]) => Instruction::CALL_ADDR(addr(*nnn)?),
+        ("SE", [V(x), V(y)]) => Instruction::SE(*x, *y),
+        ("SE", [V(x), Value(kk)]) => Instruction::SE_BYTE(*x, byte(*kk)?),
+        ("SNE", [V(x), V(y)]) => Instruction::SNE(*x, *y),
+        ("SNE", [V(x), Value(kk)]) => Instruction::SNE_BYTE(*x, byte(*kk)?),
+        ("LD", [V(x), V(y)]) => Instruction::LD(*x, *y),
+        ("LD", [V(x), Value(kk)]) => Instruction::LD_BYTE(*x, byte(*kk)?),
+        ("LD", [I, Value(nnn)]) => Instruction::LD_I(addr(*nnn)?),
+        ("LD", [I, Long(nnnn)]) => Instruction::LD_I_LONG(fit(*nnnn, 16)?),
+        ("LD", [V(x), Dt]) => Instruction::LD_DT(*x),
+        ("LD", [V(x), K]) => Instruction::LD_KEY(*x),
+        ("LD", [Dt, V(x)]) => Instruction::LD_DT_SET(*x),
+        ("LD", [St, V(x)]) => Instruction::LD_ST_SET(*x),
+        ("LD", [F, V(x)]) => Instruction::LD_F(*x),
+        ("LD", [Hf, V(x)]) => Instruction::LD_HF(*x),
+        ("LD", [B, V(x)]) => Instruction::LD_B(*x),
+        ("LD", [IndirectI, V(x)]) => Instruction::LD_STORE_I(*x),
+        ("LD", [V(x), IndirectI]) => Instruction::LD_READ_I(*x),
+        ("LD", [R, V(x)]) => Instruction::LD_STORE_R(*x),
+        ("LD", [V(x), R]) => Instruction::LD_READ_R(*x),
+        ("ADD", [V(x), V(y)]) => Instruction::ADD(*x, *y),
+        ("ADD", [V(x), Value(kk)]) => Instruction::ADD_BYTE(*x, byte(*kk)?),
+        ("ADD", [I, V(x)]) => Instruction::ADD_I(*x),
+        ("OR", [V(x), V(y)]) => Instruction::OR(*x, *y),
+        ("AND", [V(x), V(y)]) => Instruction::AND(*x, *y),
+        ("XOR", [V(x), V(y)]) => Instruction::XOR(*x, *y),
+        ("SUB", [V(x), V(y)]) => Instruction::SUB(*x, *y),
+        ("SUBN", [V(x), V(y)]) => Instruction::SUBN(*x, *y),
+        ("SHR", [V(x)]) => Instruction::SHR(*x, *x),
+        ("SHR", [V(x), V(y)]) => Instruction::SHR(*x, *y),
+        ("SHL", [V(x)]) => Instruction::SHL(*x, *x),
+        ("SHL", [V(x), V(y)]) => Instruction::SHL(*x, *y),
+        ("RND", [V(x), Value(kk)]) => Instruction::RND_BYTE(*x, byte(*kk)?),
+        ("DRW", [V(x), V(y), Value(n)]) => Instruction::DRW(*x, *y, nibble(*n)?),
+        ("SKP", [V(x)]) => Instruction::SKP(*x),
+        ("SKNP", [V(x)]) => Instruction::SKNP(*x),
+        ("SCD", [Value(n)]) => Instruction::SCD(nibble(*n)?),
+        ("SCR", []) => Instruction::SCR,
+        ("SCL", []) => Instruction::SCL,
+        ("EXIT", []) => Instruction::EXIT,
+        ("LOW", []) => Instruction::LOW,
+        ("HIGH", []) => Instruction::HIGH,
+        ("SAVE", [V(x), V(y)]) => Instruction::SAVE(*x, *y),
+        ("LOAD", [V(x), V(y)]) => Instruction::LOAD(*x, *y),
+        ("PLANE", [Value(n)]) => Instruction::PLANE(nibble(*n)?),
+        ("AUDIO", []) => Instruction::AUDIO,
+        ("PITCH", [V(x)]) => Instruction::PITCH(*x),
+        _ => return Err(format!("invalid operands for {}", mnemonic)),
+    };
+
+    Ok(inst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+
+    use std::env;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            SPEED = 4
+            start:
+                LD V0, SPEED     ; comment
+                LD I, sprite
+                DRW V0, V1, 2
+                JP start
+            sprite: db 0xff, 0b10000001
+                dw 0x1234
+        ";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x60, 0x04, 0xA2, 0x08, 0xD0, 0x12, 0x12, 0x00, 0xFF, 0x81, 0x12, 0x34
+            ])
+        );
+    }
+
+    #[test]
+    fn test_long_load() {
+        let source = "LD I, LONG data\nPLANE 3\ndata: db 1";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![0xF0, 0x00, 0x02, 0x06, 0xF3, 0x01, 0x01])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("CLS\nLD V0, 256"),
+            "<source>:2: 0x100 does not fit in 8 bits"
+        );
+        assert_eq!(error("JP nowhere"), "<source>:1: unknown value nowhere");
+        assert_eq!(error("a:\na:"), "<source>:2: a is already defined");
+        assert_eq!(error("LD K, V0"), "<source>:1: invalid operands for LD");
+        assert_eq!(
+            error("A = B\nB = A\nLD V0, A"),
+            "<source>:3: constant A refers to itself"
+        );
+    }
+
+    #[test]
+    fn test_long_constant_chain() {
+        // C0 = C1, C1 = C2, ... C19 = 7
+        let mut source: String = (0..19).map(|n| format!("C{} = C{}\n", n, n + 1)).collect();
+        source += "C19 = 7\nLD V0, C0\n";
+
+        assert_eq!(assemble(&source).unwrap(), [0x60, 0x07]);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            "include \"sprites.asm\"\nLD I, ball\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sprites.asm"), "JP 0x206\nball: db 0x60, 0x60\n").unwrap();
+        fs::write(dir.join("loop.asm"), "include loop.asm\n").unwrap();
+
+        let main = assemble_file(&dir.join("main.asm"));
+        let looped = assemble_file(&dir.join("loop.asm"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(main, Ok(vec![0x12, 0x06, 0x60, 0x60, 0xA2, 0x02]));
+        assert!(looped.unwrap_err().message.ends_with("includes itself"));
+    }
+
+    #[test]
+    fn test_disassembly_round_trips() {
+        let rom = include_bytes!("../roms/test_opcode.ch8");
+
+        assert_eq!(assemble(&disassemble(rom)).as_deref(), Ok(&rom[..]));
+    }
+}
@@ -0,0 +1,75 @@
+use chip8::beeper::{Beeper, Tone};
+use chip8::Cpu;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+const SAMPLE_RATE: i32 = 44100;
+
+struct Callback(Beeper);
+
+impl AudioCallback for Callback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+pub struct Audio {
+    // None when no audio device could be opened; the emulator then runs silent
+    device: Option<AudioDevice<Callback>>,
+}
+
+impl Audio {
+    pub fn init(sdl_context: &sdl2::Sdl, tone: Tone, muted: bool) -> Audio {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = sdl_context
+            .audio()
+            .and_then(|audio_subsystem| {
+                audio_subsystem.open_playback(None, &desired, |spec| {
+                    let mut beeper = Beeper::init(tone, spec.freq as u32);
+                    beeper.set_muted(muted);
+                    Callback(beeper)
+                })
+            })
+            .map_err(|e| eprintln!("could not initialize audio, sound is disabled: {}", e))
+            .ok();
+
+        if let Some(device) = &device {
+            device.resume();
+        }
+
+        Audio { device }
+    }
+
+    /// Starts or stops the buzzer to follow the sound timer of `cpu`.
+    pub fn update(&mut self, cpu: &Cpu) {
+        if let Some(device) = &mut self.device {
+            device.lock().0.update(cpu);
+        }
+    }
+
+    /// Silences the device while paused, whatever the sound timer says.
+    pub fn set_paused(&mut self, paused: bool) {
+        if let Some(device) = &self.device {
+            if paused {
+                device.pause();
+            } else {
+                device.resume();
+            }
+        }
+    }
+
+    pub fn toggle_mute(&mut self) {
+        if let Some(device) = &mut self.device {
+            let mut callback = device.lock();
+            let muted = callback.0.muted();
+            callback.0.set_muted(!muted);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::cpu::Cpu;
+
+use std::f32::consts::PI;
+
+/// Shape of the tone played while the sound timer is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// Looks up a waveform by name: `square`, `triangle`, `sawtooth` or
+    /// `sine`.
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    /// Value of the wave at `phase`, from 0 up to but excluding 1, scaled to
+    /// -1 to 1.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+/// The tone played while the sound timer is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    /// Pitch in Hz.
+    pub frequency: f32,
+    /// Amplitude from 0 (silent) to 1 (full scale).
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Rate an XO-CHIP audio pattern plays back at with the default pitch, in bits
+/// per second.
+const PATTERN_RATE: f32 = 4000.0;
+
+/// Generates the samples of the CHIP-8 buzzer, independently of any audio
+/// device.
+///
+/// The buzzer sounds while the sound timer is non-zero. It plays the [`Tone`]
+/// unless the program has loaded an XO-CHIP audio pattern, which is played
+/// as a 1-bit waveform at the rate selected by the pitch register instead.
+pub struct Beeper {
+    tone: Tone,
+    sample_rate: f32,
+    // position within the current period of the tone, 0 to 1
+    phase: f32,
+    playing: bool,
+    muted: bool,
+    // XO-CHIP pattern, its rate in bits per second and the current bit
+    pattern: Option<([u8; 16], f32)>,
+    position: f32,
+}
+
+impl Beeper {
+    /// Creates a silent beeper producing `sample_rate` samples per second.
+    pub fn init(tone: Tone, sample_rate: u32) -> Beeper {
+        Beeper {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            playing: false,
+            muted: false,
+            pattern: None,
+            position: 0.0,
+        }
+    }
+
+    /// Picks up the sound timer and XO-CHIP audio registers of `cpu`.
+    pub fn update(&mut self, cpu: &Cpu) {
+        self.playing = cpu.st() > 0;
+        self.pattern = cpu.audio_pattern().map(|pattern| {
+            let rate = PATTERN_RATE * 2f32.powf((cpu.pitch() as f32 - 64.0) / 48.0);
+            (*pattern, rate)
+        });
+    }
+
+    /// Whether the buzzer is currently audible.
+    pub fn playing(&self) -> bool {
+        self.playing && !self.muted
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Fills `out` with the next mono samples.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        if !self.playing() {
+            out.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        for sample in out.iter_mut() {
+            let value = match &self.pattern {
+                Some((pattern, rate)) => {
+                    let bit = self.position as usize % 128;
+                    self.position = (self.position + rate / self.sample_rate) % 128.0;
+
+                    match (pattern[bit / 8] >> (7 - bit % 8)) & 1 {
+                        1 => 1.0,
+                        _ => -1.0,
+                    }
+                }
+                None => {
+                    let value = self.tone.waveform.sample(self.phase);
+                    self.phase = (self.phase + self.tone.frequency / self.sample_rate) % 1.0;
+
+                    value
+                }
+            };
+
+            *sample = value * self.tone.volume;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+
+    fn beeping_cpu() -> Cpu {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::LD_BYTE(0, 10)).unwrap();
+        cpu.execute(Instruction::LD_ST_SET(0)).unwrap();
+
+        cpu
+    }
+
+    #[test]
+    fn test_silent_without_sound_timer() {
+        let mut beeper = Beeper::init(Tone::default(), 8000);
+        beeper.update(&Cpu::init(Quirks::default()));
+
+        let mut out = [1.0; 16];
+        beeper.fill(&mut out);
+
+        assert!(!beeper.playing());
+        assert_eq!(out, [0.0; 16]);
+    }
+
+    #[test]
+    fn test_square_wave() {
+        let tone = Tone {
+            waveform: Waveform::Square,
+            frequency: 1000.0,
+            volume: 0.5,
+        };
+        let mut beeper = Beeper::init(tone, 8000);
+        beeper.update(&beeping_cpu());
+
+        let mut out = [0.0; 8];
+        beeper.fill(&mut out);
+
+        assert_eq!(out, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_waveforms() {
+        assert_eq!(Waveform::Triangle.sample(0.0), -1.0);
+        assert_eq!(Waveform::Triangle.sample(0.5), 1.0);
+        assert_eq!(Waveform::Sawtooth.sample(0.0), -1.0);
+        assert_eq!(Waveform::Sine.sample(0.25), 1.0);
+    }
+
+    #[test]
+    fn test_muted() {
+        let mut beeper = Beeper::init(Tone::default(), 8000);
+        beeper.update(&beeping_cpu());
+        beeper.set_muted(true);
+
+        let mut out = [1.0; 16];
+        beeper.fill(&mut out);
+
+        assert!(!beeper.playing());
+        assert_eq!(out, [0.0; 16]);
+    }
+
+    #[test]
+    fn test_stops_with_sound_timer() {
+        let mut cpu = beeping_cpu();
+        let mut beeper = Beeper::init(Tone::default(), 8000);
+        beeper.update(&cpu);
+        assert!(beeper.playing());
+
+        for _ in 0..10 {
+            cpu.tick_timers();
+        }
+        beeper.update(&cpu);
+        assert!(!beeper.playing());
+    }
+
+    #[test]
+    fn test_xo_chip_pattern() {
+        let mut cpu = beeping_cpu();
+        cpu.execute(Instruction::LD_I(0x300)).unwrap();
+        cpu.execute(Instruction::AUDIO).unwrap();
+
+        // an all zero pattern at the default pitch is one long low pulse
+        let mut beeper = Beeper::init(Tone::default(), 4000);
+        beeper.update(&cpu);
+
+        let mut out = [0.0; 4];
+        beeper.fill(&mut out);
+
+        assert_eq!(out, [-0.25; 4]);
+    }
+}
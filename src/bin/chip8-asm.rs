@@ -0,0 +1,31 @@
+use chip8::asm::assemble_file;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: chip8-asm <source> [-o <rom>]
+
+Writes the rom next to the source with a .ch8 extension unless -o is given.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (source, rom) = match args.as_slice() {
+        [source] => (Path::new(source), Path::new(source).with_extension("ch8")),
+        [source, flag, rom] if flag == "-o" => (Path::new(source), PathBuf::from(rom)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let bytes = assemble_file(source).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    if let Err(error) = fs::write(&rom, bytes) {
+        eprintln!("{}: {}", rom.display(), error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,24 @@
+use chip8::disasm::disassemble;
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let rom = match args.as_slice() {
+        [rom] => rom,
+        _ => {
+            eprintln!("usage: chip8-disasm <rom>");
+            process::exit(2);
+        }
+    };
+
+    match fs::read(rom) {
+        Ok(bytes) => print!("{}", disassemble(&bytes)),
+        Err(error) => {
+            eprintln!("{}: {}", rom, error);
+            process::exit(1);
+        }
+    }
+}
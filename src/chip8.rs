@@ -1,44 +1,523 @@
-use crate::cpu::Cpu;
-use crate::display::Display;
-use crate::input::Input;
+use crate::audio::Audio;
+use crate::config::{Config, Keymap};
+use crate::display::{Display, Indicator};
+use crate::input::{Controllers, Input};
+use crate::options::Options;
 
+use chip8::cpu::FRAME_RATE;
+use chip8::debugger::{Command, Debugger};
+use chip8::movie::Movie;
+use chip8::profiler::Profiler;
+use chip8::rewind::Rewind;
+use chip8::rom::Rom;
+use chip8::{Chip8Error, Cpu};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::Sdl;
 
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Emulated frames per real frame that fast-forward and slow motion step
+/// through. Whole frames run at any speed, so timers keep pace with the
+/// instructions and runs stay the same as at normal speed.
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
 pub struct Chip8 {
     cpu: Cpu,
     display: Display,
     input: Input,
+    controllers: Controllers,
+    audio: Audio,
     sdl_context: Sdl,
+    // instructions executed per 60 Hz frame
+    ipf: u32,
+    debugger: Option<Debugger>,
+    // path of the loaded rom, which save states are stored next to
+    path: String,
+    // the loaded rom, reloaded on reset
+    rom: Rom,
+    rewind: Rewind,
+    // whether the rewind hotkey is held
+    rewinding: bool,
+    // paused while another window has focus
+    focused: bool,
+    paused: bool,
+    // frames to run while paused
+    advance: u32,
+    // index into SPEEDS
+    speed: usize,
+    // emulated frames owed, as a fraction of one, at speeds below 1
+    frame_budget: f32,
+    seed: u64,
+    // movie being recorded and the path it is written to
+    recording: Option<(Movie, String)>,
+    // movie being replayed and the next frame of it
+    playing: Option<(Movie, usize)>,
+    // path of the movie to record once the rom is loaded
+    record: Option<String>,
+    // profile being counted and the path it is written to
+    profile: Option<(Profiler, String)>,
+    config: Config,
+    // command line bindings, applied over the config's keymap for the rom
+    bindings: Keymap,
 }
 
 impl Chip8 {
-    pub fn init() -> Chip8 {
+    pub fn init(options: &Options, movie: Option<Movie>, config: Config) -> Chip8 {
         let sdl_context = sdl2::init().unwrap();
+        let seed = match &movie {
+            Some(movie) => movie.seed,
+            None => options.seed.unwrap_or_else(rand::random),
+        };
 
         Chip8 {
-            cpu: Cpu::init(),
-            display: Display::init(&sdl_context),
-            input: Input::init(),
+            cpu: Cpu::init(options.quirks),
+            display: Display::init(&sdl_context, &config.display),
+            input: Input::init(&Keymap::default(), &Keymap::gamepad()).unwrap(),
+            controllers: Controllers::init(sdl_context.game_controller().unwrap()),
+            audio: Audio::init(&sdl_context, options.tone, options.mute),
             sdl_context,
+            ipf: options.ipf,
+            debugger: if options.debug {
+                Some(Debugger::init())
+            } else {
+                None
+            },
+            path: options.rom.clone(),
+            rom: Rom::init(Vec::new()),
+            rewind: Rewind::init(options.rewind_frames, options.rewind_memory),
+            rewinding: false,
+            focused: true,
+            paused: false,
+            advance: 0,
+            speed: NORMAL_SPEED,
+            frame_budget: 0.0,
+            seed,
+            recording: None,
+            playing: movie.map(|movie| (movie, 0)),
+            record: options.record.clone(),
+            profile: options
+                .profile
+                .as_ref()
+                .map(|path| (Profiler::init(), path.clone())),
+            config,
+            bindings: options.bindings.clone(),
+        }
+    }
+
+    pub fn load(&mut self, rom: &Rom) -> Result<(), String> {
+        self.cpu.load_rom(rom).map_err(|e| e.to_string())?;
+        self.cpu.reseed(self.seed);
+        self.rom = rom.clone();
+
+        let mut keymap = self.config.keymap(self.cpu.rom_hash());
+        keymap.overlay(&self.bindings);
+        self.input = Input::init(&keymap, &self.config.buttons(self.cpu.rom_hash()))?;
+
+        if let Some((movie, _)) = &self.playing {
+            if movie.rom_hash != self.cpu.rom_hash() {
+                return Err(format!(
+                    "movie is for rom {}, not the loaded rom {}",
+                    movie.rom_hash,
+                    self.cpu.rom_hash()
+                ));
+            }
+        }
+        if let Some(path) = self.record.take() {
+            let mut movie =
+                Movie::init(self.cpu.rom_hash(), self.seed, self.ipf, self.cpu.quirks());
+            movie.load_address = rom.load_address;
+            movie.start_address = rom.start_address;
+            self.recording = Some((movie, path));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the movie being recorded, if any.
+    fn save_movie(&self) {
+        if let Some((movie, path)) = &self.recording {
+            if let Err(e) = fs::write(path, movie.encode()) {
+                eprintln!("{}: {}", path, e);
+            }
+        }
+    }
+
+    /// Feeds the next frame of the movie being replayed into the keys, then
+    /// records them into the movie being recorded.
+    fn movie_frame(&mut self) {
+        if let Some((movie, frame)) = &mut self.playing {
+            match movie.keys(*frame) {
+                Some(keys) => {
+                    self.cpu.keys = keys;
+                    *frame += 1;
+                }
+                None => {
+                    println!("Movie finished after {} frames", frame);
+                    self.playing = None;
+                }
+            }
+        }
+
+        if let Some((movie, _)) = &mut self.recording {
+            movie.record(&self.cpu.keys);
+            // written every second as well, so closing the window loses little
+            if movie.len() % FRAME_RATE as usize == 0 {
+                self.save_movie();
+            }
+        }
+    }
+
+    /// Whether a movie is recording or replaying, during which the history
+    /// can't be changed by rewinding, loading a state or resetting. Says so
+    /// if it is, naming the refused `action`.
+    fn in_movie(&self, action: &str) -> bool {
+        let in_movie = self.recording.is_some() || self.playing.is_some();
+        if in_movie {
+            eprintln!("Can't {} during a movie", action);
+        }
+
+        in_movie
+    }
+
+    /// Restarts the loaded rom from a fresh machine with the same quirks and
+    /// seed, clearing the rewind history.
+    fn reset(&mut self) {
+        if self.in_movie("reset") {
+            return;
+        }
+        self.cpu = Cpu::init(self.cpu.quirks());
+        // the rom already fit when it was first loaded
+        let _ = self.cpu.load_rom(&self.rom);
+        self.cpu.reseed(self.seed);
+        self.rewind.clear();
+        println!("Reset {}", self.path);
+    }
+
+    /// Emulated frames to run this real frame.
+    fn frames_due(&mut self) -> u32 {
+        if self.paused {
+            return std::mem::take(&mut self.advance);
+        }
+        self.frame_budget += SPEEDS[self.speed];
+        let frames = self.frame_budget.floor();
+        self.frame_budget -= frames;
+
+        frames as u32
+    }
+
+    /// What the title and indicator show about the emulator's own state.
+    fn indicator(&self) -> (Indicator, String) {
+        let speed = SPEEDS[self.speed];
+        if !self.focused {
+            (
+                Indicator::Paused,
+                "chip8 (paused in the background)".to_string(),
+            )
+        } else if self.paused {
+            (Indicator::Paused, "chip8 (paused)".to_string())
+        } else if speed > 1.0 {
+            (Indicator::Fast, format!("chip8 ({}x)", speed))
+        } else if speed < 1.0 {
+            (Indicator::Slow, format!("chip8 ({}x)", speed))
+        } else {
+            (Indicator::None, "chip8".to_string())
+        }
+    }
+
+    fn show_indicator(&mut self) {
+        let (_, title) = self.indicator();
+        self.display.set_title(&title);
+        self.cpu.should_draw = true;
+    }
+
+    /// Next to the rom, or in the working directory named by its hash when
+    /// it came from stdin.
+    fn state_path(&self, slot: u8) -> String {
+        match self.path.as_str() {
+            "-" => format!("{}.state{}", self.rom.hash(), slot),
+            path => format!("{}.state{}", path, slot),
+        }
+    }
+
+    fn save_state(&self, slot: u8) {
+        let path = self.state_path(slot);
+        match fs::write(&path, self.cpu.save_state()) {
+            Ok(()) => println!("Saved state {} to {}", slot, path),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+
+    fn load_state(&mut self, slot: u8) {
+        let path = self.state_path(slot);
+        let loaded = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|state| self.cpu.load_state(&state).map_err(|e| e.to_string()));
+        match loaded {
+            Ok(()) => println!("Loaded state {} from {}", slot, path),
+            Err(e) => eprintln!("{}: {}", path, e),
         }
     }
 
-    pub fn load(&mut self, filename: &String) {
-        self.cpu.load_rom(filename);
+    /// Handles the emulator's own hotkeys: M mutes, F1 to F9 save to a state
+    /// slot and Shift+F1 to F9 load from it. Backspace rewinds while held and
+    /// F11 toggles fullscreen. P pauses, N advances a frame while paused, =
+    /// and - speed up and slow down, 0 returns to normal speed and F10
+    /// resets. Keys bound to the keypad are left to it.
+    fn hotkey(&mut self, keycode: Keycode, keymod: Mod) {
+        if self.input.binds(keycode) {
+            return;
+        }
+        let slot = match keycode {
+            Keycode::P => {
+                self.paused = !self.paused;
+                self.show_indicator();
+                return;
+            }
+            Keycode::N => {
+                if self.paused {
+                    self.advance += 1;
+                }
+                return;
+            }
+            Keycode::Equals | Keycode::KpPlus => {
+                self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+                self.show_indicator();
+                return;
+            }
+            Keycode::Minus | Keycode::KpMinus => {
+                self.speed = self.speed.saturating_sub(1);
+                self.show_indicator();
+                return;
+            }
+            Keycode::Num0 | Keycode::Kp0 => {
+                self.speed = NORMAL_SPEED;
+                self.show_indicator();
+                return;
+            }
+            Keycode::F10 => {
+                self.reset();
+                return;
+            }
+            Keycode::F11 => {
+                self.display.toggle_fullscreen();
+                return;
+            }
+            Keycode::Backspace => {
+                self.rewinding = !self.in_movie("rewind");
+                return;
+            }
+            Keycode::M => {
+                self.audio.toggle_mute();
+                return;
+            }
+            Keycode::F1 => 1,
+            Keycode::F2 => 2,
+            Keycode::F3 => 3,
+            Keycode::F4 => 4,
+            Keycode::F5 => 5,
+            Keycode::F6 => 6,
+            Keycode::F7 => 7,
+            Keycode::F8 => 8,
+            Keycode::F9 => 9,
+            _ => return,
+        };
+
+        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+            if !self.in_movie("load a state") {
+                self.load_state(slot);
+            }
+        } else {
+            self.save_state(slot);
+        }
+    }
+
+    /// Runs a frame under the debugger after carrying out the commands typed
+    /// since the last one, returning whether the user quit.
+    fn debug_frame(&mut self, commands: &Receiver<String>) -> bool {
+        let debugger = match &mut self.debugger {
+            Some(debugger) => debugger,
+            None => return false,
+        };
+
+        for line in commands.try_iter() {
+            match Command::parse(&line) {
+                Ok(Command::Quit) => return true,
+                Ok(command) => match debugger.execute(&mut self.cpu, command) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => println!("{}", output),
+                    Err(error) => println!("{}", error),
+                },
+                Err(error) => println!("{}", error),
+            }
+            if debugger.paused() {
+                prompt();
+            }
+        }
+
+        if !debugger.paused() {
+            self.rewind.record(&self.cpu);
+        }
+        if let Some(stop) = debugger.run_frame(&mut self.cpu, self.ipf) {
+            println!("{}\n{}", stop, Debugger::status(&self.cpu));
+            prompt();
+        }
+
+        false
     }
 
-    pub fn start(&mut self) {
+    /// Pauses while the window is in the background, except under the
+    /// debugger, whose commands are typed into another window.
+    fn set_focused(&mut self, focused: bool) {
+        if self.debugger.is_some() || focused == self.focused {
+            return;
+        }
+        self.focused = focused;
+        self.audio.set_paused(!focused);
+        self.show_indicator();
+        // releases happen elsewhere while unfocused
+        self.cpu.keys = [false; 16];
+        self.input.release_all();
+        self.rewinding = false;
+    }
+
+    /// Handles window events, returning whether to quit.
+    fn window_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return true,
+            Event::Window { win_event, .. } => match win_event {
+                WindowEvent::SizeChanged(..) | WindowEvent::Exposed => self.cpu.should_draw = true,
+                WindowEvent::FocusLost => self.set_focused(false),
+                WindowEvent::FocusGained => self.set_focused(true),
+                _ => {}
+            },
+            _ => {}
+        }
+
+        false
+    }
+
+    /// Runs until the window is closed, Escape is pressed, the program exits
+    /// or the debugger quits.
+    pub fn start(&mut self) -> Result<(), Chip8Error> {
+        let result = self.run();
+        self.save_movie();
+        if let Some((profiler, path)) = &self.profile {
+            if let Err(e) = crate::save_profile(profiler, &self.cpu, path) {
+                eprintln!("{}", e);
+            }
+        }
+
+        result
+    }
+
+    fn run(&mut self) -> Result<(), Chip8Error> {
         let mut event_pump = self.sdl_context.event_pump().unwrap();
+        let frame = Duration::from_secs(1) / FRAME_RATE;
+        let mut next_frame = Instant::now();
+        let commands = self.debugger.as_ref().map(|_| {
+            println!("{}", Debugger::status(&self.cpu));
+            prompt();
+            read_commands()
+        });
         loop {
             for event in event_pump.poll_iter() {
+                if let Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } = event
+                {
+                    self.hotkey(keycode, keymod);
+                }
+                if let Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } = event
+                {
+                    self.rewinding = false;
+                }
+                if self.window_event(&event) {
+                    return Ok(());
+                }
+                self.controllers.process(&event);
                 self.input.process(&mut self.cpu.keys, event);
             }
-            self.cpu.cycle();
+            if self.rewinding {
+                // one frame back per frame, so rewinding plays at normal speed
+                match self.rewind.undo(&mut self.cpu) {
+                    Ok(true) => self.cpu.should_draw = true,
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Can't rewind: {}", e);
+                        self.rewind.clear();
+                    }
+                }
+            } else if let Some(commands) = &commands {
+                if self.debug_frame(commands) {
+                    return Ok(());
+                }
+            } else if self.focused {
+                for _ in 0..self.frames_due() {
+                    self.movie_frame();
+                    self.rewind.record(&self.cpu);
+                    match &mut self.profile {
+                        Some((profiler, _)) => profiler.run_frame(&mut self.cpu, self.ipf)?,
+                        None => self.cpu.run_frame(self.ipf)?,
+                    }
+                    if self.cpu.exited() {
+                        break;
+                    }
+                }
+            }
+            self.audio.update(&self.cpu);
             if self.cpu.should_draw {
-                self.display.render(&self.cpu.pixels);
+                let (indicator, _) = self.indicator();
+                self.display.render(&self.cpu.pixels, indicator);
                 self.cpu.should_draw = false;
             }
-            ::std::thread::sleep(std::time::Duration::new(0, 2000000));
+            if self.cpu.exited() && self.debugger.is_none() {
+                return Ok(());
+            }
+
+            next_frame += frame;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                // too far behind to catch up, e.g. after the window was dragged
+                next_frame = now;
+            }
         }
     }
 }
+
+fn prompt() {
+    print!("(chip8) ");
+    io::stdout().flush().ok();
+}
+
+/// Reads debugger commands from stdin on a separate thread, so the window
+/// keeps running while waiting for input.
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use crate::palette::Palette;
+
+/// The COSMAC VIP keypad laid over the left of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+const DEFAULT_KEYS: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+/// The d-pad and left stick move through 2, 4, 6 and 8, the usual
+/// directions of CHIP-8 games, and the face buttons press 5, A and B.
+const DEFAULT_BUTTONS: [(u8, &[&str]); 7] = [
+    (0x2, &["dpup", "lefty-"]),
+    (0x4, &["dpleft", "leftx-"]),
+    (0x5, &["a"]),
+    (0x6, &["dpright", "leftx+"]),
+    (0x8, &["dpdown", "lefty+"]),
+    (0xA, &["b"]),
+    (0xB, &["x"]),
+];
+
+/// Keyboard keys and controller buttons added to the CHIP-8 keys a ROM's
+/// database entry names for each of its controls.
+const HINTS: [(&str, &str, &[&str]); 6] = [
+    ("up", "Up", &["dpup", "lefty-"]),
+    ("down", "Down", &["dpdown", "lefty+"]),
+    ("left", "Left", &["dpleft", "leftx-"]),
+    ("right", "Right", &["dpright", "leftx+"]),
+    ("a", "Space", &["a"]),
+    ("b", "Return", &["b"]),
+];
+
+/// Host keys bound to each CHIP-8 key, by SDL key name, e.g. `W`, `Up` or
+/// `Keypad 5`, or by SDL game controller button or axis name, e.g. `dpup`,
+/// `a` or `leftx-`. A CHIP-8 key may have any number of host keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    bindings: BTreeMap<u8, Vec<String>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap {
+            bindings: (0..16)
+                .map(|key| (key, vec![DEFAULT_KEYS[key as usize].to_string()]))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// The default game controller mapping.
+    pub fn gamepad() -> Keymap {
+        Keymap {
+            bindings: DEFAULT_BUTTONS
+                .iter()
+                .map(|&(key, names)| (key, names.iter().map(|name| name.to_string()).collect()))
+                .collect(),
+        }
+    }
+
+    /// A keymap binding nothing, for overlaying on another.
+    pub fn empty() -> Keymap {
+        Keymap {
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    /// Binds `key` to exactly `host_keys`, replacing its previous bindings.
+    pub fn bind(&mut self, key: u8, host_keys: Vec<String>) {
+        self.bindings.insert(key, host_keys);
+    }
+
+    /// Replaces the bindings of every CHIP-8 key `other` binds.
+    pub fn overlay(&mut self, other: &Keymap) {
+        for (&key, host_keys) in &other.bindings {
+            self.bind(key, host_keys.clone());
+        }
+    }
+
+    /// Adds `host_keys` to those of `key`, moving them off any other key.
+    fn add(&mut self, key: u8, host_keys: &[&str]) {
+        for bound in self.bindings.values_mut() {
+            bound.retain(|name| !host_keys.contains(&name.as_str()));
+        }
+        let bound = self.bindings.entry(key).or_default();
+        bound.extend(host_keys.iter().map(|name| name.to_string()));
+    }
+
+    /// Each host key name with the CHIP-8 key it presses.
+    pub fn host_keys(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.bindings
+            .iter()
+            .flat_map(|(&key, host_keys)| host_keys.iter().map(move |name| (name.as_str(), key)))
+    }
+
+    /// Parses a `--bind` value such as `5=Up,W`.
+    pub fn parse_binding(binding: &str) -> Result<(u8, Vec<String>), String> {
+        let (key, host_keys) = binding
+            .split_once('=')
+            .ok_or_else(|| format!("invalid binding {}, expected <key>=<host keys>", binding))?;
+        let host_keys = host_keys
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        Ok((parse_key(key)?, host_keys))
+    }
+
+    /// Reads a table, named `section` in errors, of CHIP-8 keys to a host
+    /// key name or an array of them.
+    fn from_toml(table: &Value, section: &str) -> Result<Keymap, String> {
+        let table = table
+            .as_table()
+            .ok_or_else(|| format!("{} must be a table", section))?;
+        let mut keymap = Keymap::empty();
+        for (key, host_keys) in table {
+            let host_keys = match host_keys {
+                Value::String(name) => vec![name.clone()],
+                Value::Array(names) => names
+                    .iter()
+                    .map(|name| name.as_str().map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| format!("{}.{} must be names", section, key))?,
+                _ => {
+                    return Err(format!(
+                        "{}.{} must be a name or an array of them",
+                        section, key
+                    ))
+                }
+            };
+            keymap.bind(parse_key(key)?, host_keys);
+        }
+
+        Ok(keymap)
+    }
+}
+
+fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key.trim(), 16)
+        .ok()
+        .filter(|&key| key < 16)
+        .ok_or_else(|| format!("invalid CHIP-8 key {}", key))
+}
+
+/// Settings from the config file.
+///
+/// ```toml
+/// [keys]
+/// 5 = ["W", "Up"]
+/// 8 = ["S", "Down"]
+///
+/// [buttons]
+/// 5 = ["a", "rightshoulder"]
+///
+/// [display]
+/// palette = "amber"
+/// scale = 12
+///
+/// # only while running the rom with this SHA-1
+/// [roms.0123456789abcdef0123456789abcdef01234567.keys]
+/// 6 = "Space"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub display: DisplayConfig,
+    keys: Keymap,
+    buttons: Keymap,
+    // overrides by hex SHA-1 of the rom
+    roms: HashMap<String, RomConfig>,
+}
+
+/// Keymap overrides for a single ROM.
+#[derive(Debug, Clone, PartialEq)]
+struct RomConfig {
+    keys: Keymap,
+    buttons: Keymap,
+}
+
+/// Largest `scale`, which keeps the window size well within `u32`.
+const MAX_SCALE: u32 = 100;
+
+/// How the window draws the screen.
+// the terminal frontend only draws in text
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayConfig {
+    pub palette: Palette,
+    /// Window pixels per CHIP-8 pixel at 64x32, up to `MAX_SCALE`.
+    pub scale: u32,
+    /// Scale the screen to fill the window rather than by whole pixels.
+    pub fit: bool,
+    /// Draw lines between pixels.
+    pub grid: bool,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> DisplayConfig {
+        DisplayConfig {
+            palette: Palette::default(),
+            scale: 10,
+            fit: false,
+            grid: false,
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// Applies `settings` of name and value, taking `palette` first so that
+    /// single colors can be changed on top of a preset.
+    pub fn apply(&mut self, settings: &[(String, String)]) -> Result<(), String> {
+        let (presets, rest): (Vec<_>, Vec<_>) =
+            settings.iter().partition(|(name, _)| name == "palette");
+        for (name, value) in presets.into_iter().chain(rest) {
+            self.set(name, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let colors = &mut self.palette.colors;
+        let flag = |value: &str| {
+            value
+                .parse()
+                .map_err(|_| format!("{} must be true or false", name))
+        };
+        match name {
+            "palette" => {
+                self.palette =
+                    Palette::preset(value).ok_or_else(|| format!("unknown palette {}", value))?
+            }
+            "background" => colors[0] = Palette::parse_color(value)?,
+            "foreground" => colors[1] = Palette::parse_color(value)?,
+            "colors" => {
+                let parsed = value
+                    .split(',')
+                    .map(Palette::parse_color)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if parsed.len() != 4 {
+                    return Err("colors must be 4 colors".to_string());
+                }
+                colors.copy_from_slice(&parsed);
+            }
+            "scale" => {
+                self.scale = value
+                    .parse()
+                    .ok()
+                    .filter(|scale| (1..=MAX_SCALE).contains(scale))
+                    .ok_or_else(|| format!("scale must be 1 to {}, not {}", MAX_SCALE, value))?
+            }
+            "fit" => self.fit = flag(value)?,
+            "grid" => self.grid = flag(value)?,
+            _ => return Err(format!("unknown display setting {}", name)),
+        }
+
+        Ok(())
+    }
+
+    /// Reads a `[display]` table.
+    fn from_toml(table: &Value) -> Result<Vec<(String, String)>, String> {
+        let table = table
+            .as_table()
+            .ok_or_else(|| "display must be a table".to_string())?;
+        table
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(text) => text.clone(),
+                    Value::Array(items) => items
+                        .iter()
+                        .map(|item| item.as_str())
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| format!("display.{} must be strings", name))?
+                        .join(","),
+                    other => other.to_string(),
+                };
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            display: DisplayConfig::default(),
+            keys: Keymap::default(),
+            buttons: Keymap::gamepad(),
+            roms: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back to
+    /// `~/.config/chip8/config.toml`.
+    pub fn path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(dir.join("chip8").join("config.toml"))
+    }
+
+    /// Reads the config at `path`, or the default one if there is no file.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.to_string()),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let value: Value = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut config = Config::default();
+
+        let section = |table: &Value, name: &str, prefix: &str| match table.get(name) {
+            Some(keys) => Keymap::from_toml(keys, &format!("{}{}", prefix, name)),
+            None => Ok(Keymap::empty()),
+        };
+
+        if let Some(display) = value.get("display") {
+            config
+                .display
+                .apply(&DisplayConfig::from_toml(display)?)
+                .map_err(|e| format!("display: {}", e))?;
+        }
+        config.keys.overlay(&section(&value, "keys", "")?);
+        config.buttons.overlay(&section(&value, "buttons", "")?);
+        if let Some(roms) = value.get("roms") {
+            let roms = roms
+                .as_table()
+                .ok_or_else(|| "roms must be a table".to_string())?;
+            for (hash, rom) in roms {
+                let prefix = format!("roms.{}.", hash);
+                let rom = RomConfig {
+                    keys: section(rom, "keys", &prefix)?,
+                    buttons: section(rom, "buttons", &prefix)?,
+                };
+                config.roms.insert(hash.to_lowercase(), rom);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Binds the arrow keys, Space and Return, and the d-pad, left stick and
+    /// A and B buttons, to the CHIP-8 keys a ROM's database entry names for
+    /// `up`, `down`, `left`, `right`, `a` and `b`. The config file's
+    /// bindings for the rom still win.
+    pub fn hint(&mut self, hints: &BTreeMap<String, u8>) {
+        for &(name, host_key, buttons) in &HINTS {
+            if let Some(&key) = hints.get(name) {
+                self.keys.add(key, &[host_key]);
+                self.buttons.add(key, buttons);
+            }
+        }
+    }
+
+    /// The keyboard keymap for the rom hashing to `rom_hash`.
+    pub fn keymap(&self, rom_hash: &str) -> Keymap {
+        let mut keymap = self.keys.clone();
+        if let Some(rom) = self.roms.get(rom_hash) {
+            keymap.overlay(&rom.keys);
+        }
+
+        keymap
+    }
+
+    /// The game controller mapping for the rom hashing to `rom_hash`.
+    // only the window reads game controllers
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub fn buttons(&self, rom_hash: &str) -> Keymap {
+        let mut buttons = self.buttons.clone();
+        if let Some(rom) = self.roms.get(rom_hash) {
+            buttons.overlay(&rom.buttons);
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn bound(keymap: &Keymap, key: u8) -> Vec<&str> {
+        keymap
+            .host_keys()
+            .filter(|&(_, bound)| bound == key)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn test_default() {
+        let keymap = Config::default().keymap(HASH);
+
+        assert_eq!(bound(&keymap, 0), ["X"]);
+        assert_eq!(bound(&keymap, 0xF), ["V"]);
+        assert_eq!(keymap.host_keys().count(), 16);
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(&format!(
+            "[keys]\n5 = [\"W\", \"Up\"]\nA = \"Space\"\n\n[roms.{}.keys]\n5 = \"I\"\n",
+            HASH
+        ))
+        .unwrap();
+
+        let keymap = config.keymap("another rom");
+        assert_eq!(bound(&keymap, 5), ["W", "Up"]);
+        assert_eq!(bound(&keymap, 0xA), ["Space"]);
+        assert_eq!(bound(&keymap, 6), ["E"]);
+        assert_eq!(bound(&config.keymap(HASH), 5), ["I"]);
+    }
+
+    #[test]
+    fn test_parse_buttons() {
+        let config = Config::parse(&format!(
+            "[buttons]\n5 = \"y\"\n\n[roms.{}.buttons]\n2 = [\"dpup\", \"righty-\"]\n",
+            HASH
+        ))
+        .unwrap();
+
+        let buttons = config.buttons("another rom");
+        assert_eq!(bound(&buttons, 5), ["y"]);
+        assert_eq!(bound(&buttons, 2), ["dpup", "lefty-"]);
+        assert_eq!(bound(&config.buttons(HASH), 2), ["dpup", "righty-"]);
+        assert_eq!(bound(&config.buttons(HASH), 5), ["y"]);
+    }
+
+    #[test]
+    fn test_hint() {
+        let mut config = Config::parse(&format!("[roms.{}.keys]\n7 = \"Left\"\n", HASH)).unwrap();
+        let hints = [("up".to_string(), 5), ("left".to_string(), 4)];
+        config.hint(&hints.iter().cloned().collect());
+
+        let keymap = config.keymap("another rom");
+        assert_eq!(bound(&keymap, 5), ["W", "Up"]);
+        assert_eq!(bound(&keymap, 4), ["Q", "Left"]);
+        assert_eq!(bound(&config.keymap(HASH), 7), ["Left"]);
+        let buttons = config.buttons(HASH);
+        assert_eq!(bound(&buttons, 5), ["a", "dpup", "lefty-"]);
+        assert!(bound(&buttons, 2).is_empty());
+    }
+
+    #[test]
+    fn test_parse_display() {
+        let config = Config::parse(
+            "[display]\nforeground = \"#123456\"\npalette = \"amber\"\nscale = 4\ngrid = true\n",
+        )
+        .unwrap();
+
+        let display = config.display;
+        assert_eq!(display.palette.colors[0], (0x1a, 0x10, 0x00));
+        assert_eq!(display.palette.colors[1], (0x12, 0x34, 0x56));
+        assert_eq!((display.scale, display.fit, display.grid), (4, false, true));
+
+        let config =
+            Config::parse("[display]\ncolors = [\"#000\", \"#fff\", \"#f00\", \"#00f\"]\n");
+        assert_eq!(config.unwrap().display.palette.colors[3], (0, 0, 255));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("[keys]\nG = \"W\"").is_err());
+        assert!(Config::parse("[keys]\n5 = 1").is_err());
+        assert!(Config::parse("keys = [").is_err());
+        assert!(Config::parse("[display]\nscale = 0").is_err());
+        assert!(Config::parse("[display]\nscale = 100").is_ok());
+        assert!(Config::parse("[display]\nscale = 100000000").is_err());
+        assert!(Config::parse("[display]\npalette = \"sepia\"").is_err());
+        assert!(Config::parse("[display]\ncolors = [\"#000\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!(
+            Keymap::parse_binding("c=Left, Keypad 4"),
+            Ok((0xC, vec!["Left".to_string(), "Keypad 4".to_string()]))
+        );
+        assert!(Keymap::parse_binding("5").is_err());
+        assert!(Keymap::parse_binding("10=W").is_err());
+    }
+}
@@ -1,18 +1,35 @@
+use crate::error::Chip8Error;
+use crate::framebuffer::{Framebuffer, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
 use crate::instruction::Instruction;
-use crate::rom::rom::load_rom;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::rom::{self, Rom, RomError};
+use crate::state::{Reader, StateError, Writer};
 
-use rand::Rng;
+use std::ops::Range;
 
+/// Size of memory. XO-CHIP extends the original 4 KiB to 64 KiB.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Address programs are loaded at and execution starts from.
 pub const START_ADDRESS: u16 = 0x200;
 const FONT_START_ADDRESS: u16 = 0x50;
+const BIG_FONT_START_ADDRESS: u16 = 0xA0;
 
-pub const WIDTH: usize = 64;
-pub const HEIGHT: usize = 32;
+/// Rate the delay and sound timers count down at, in Hz. Frontends run one
+/// frame per timer tick.
+pub const FRAME_RATE: u32 = 60;
 
+/// The complete state of a CHIP-8 machine.
+///
+/// A frontend calls [`Cpu::run_frame`] [`FRAME_RATE`] times a second,
+/// forwards key presses into [`Cpu::keys`] and presents [`Cpu::pixels`]
+/// whenever [`Cpu::should_draw`] is set.
 pub struct Cpu {
     // 0x000 to 0x1ff unused
-    // programs usually start from 0x200 but sometimes 0x600
-    mem: [u8; 4096],
+    // programs usually start from 0x200 but ETI 660 ones from 0x600
+    // anything past 0xfff is only reachable by XO-CHIP programs
+    mem: Vec<u8>,
     // general purpose registers
     v: [u8; 16],
     // used to store memory addresses
@@ -24,15 +41,44 @@ pub struct Cpu {
     pc: u16,
     sp: u8,
     stack: [u16; 16],
-    pub pixels: [[bool; 64]; 32],
+    // address and word of the instruction being executed, for error reports
+    op_pc: u16,
+    opcode: u16,
+    // memory written by the last instruction, for debugger watchpoints
+    last_write: Option<Range<usize>>,
+    // memory read as data by the last instruction, for the profiler
+    last_read: Option<Range<usize>>,
+    quirks: Quirks,
+    // SUPER-CHIP RPL user flags, 8 on the HP-48 and 16 in XO-CHIP
+    rpl: [u8; 16],
+    // set by the SUPER-CHIP EXIT instruction
+    exited: bool,
+    // XO-CHIP bitplanes drawn to, 1 and 2 as a bit mask
+    planes: u8,
+    // XO-CHIP audio pattern buffer and its playback pitch
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    // hex SHA-1 of the loaded rom, to match save states to it
+    rom_hash: String,
+    // source of RND, saved with the rest of the state
+    rng: Rng,
+    /// The display, 64x32 or 128x64 in SUPER-CHIP high resolution mode,
+    /// with up to two XO-CHIP bitplanes.
+    pub pixels: Framebuffer,
+    /// Set whenever `pixels` changed; the frontend clears it after drawing.
     pub should_draw: bool,
+    /// Pressed state of the hex keys `0x0` to `0xF`.
     pub keys: [bool; 16],
 }
 
 impl Cpu {
-    pub fn init() -> Cpu {
+    /// Creates a powered-on machine with the font loaded and no program.
+    ///
+    /// `quirks` selects how ambiguous opcodes behave. `RND` is randomly
+    /// seeded; call [`Cpu::reseed`] for a reproducible run.
+    pub fn init(quirks: Quirks) -> Cpu {
         let mut cpu = Cpu {
-            mem: [0; 4096],
+            mem: vec![0; MEMORY_SIZE],
             v: [0; 16],
             i: 0,
             dt: 0,
@@ -40,7 +86,19 @@ impl Cpu {
             pc: START_ADDRESS,
             sp: 0,
             stack: [0; 16],
-            pixels: [[false; WIDTH]; HEIGHT],
+            op_pc: START_ADDRESS,
+            opcode: 0,
+            last_write: None,
+            last_read: None,
+            quirks,
+            rpl: [0; 16],
+            exited: false,
+            planes: 0b01,
+            pattern: None,
+            pitch: 64,
+            rom_hash: rom::hash(&[]),
+            rng: Rng::init(rand::random()),
+            pixels: Framebuffer::init(WIDTH, HEIGHT),
             should_draw: true,
             keys: [false; 16],
         };
@@ -62,29 +120,235 @@ impl Cpu {
         let start = FONT_START_ADDRESS as usize;
         let end = start + fonts.len();
         self.mem[start..end].clone_from_slice(&fonts);
+
+        // 8x10 digits used by LD HF, Vx; SUPER-CHIP only has 0-9
+        let big_fonts: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x18, 0x78, 0x78, 0x18,
+            0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0,
+            0xFF, 0xFF, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC3, 0xC3,
+            0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+            0x03, 0x03, 0xFF, 0xFF, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, 0xC3, 0xC3,
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03,
+            0xFF, 0xFF, 0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xFC, 0xFC,
+            0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0,
+            0xC0, 0xC3, 0xFF, 0x3C, 0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF, 0xC0, 0xC0,
+            0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+        ];
+
+        let start = BIG_FONT_START_ADDRESS as usize;
+        let end = start + big_fonts.len();
+        self.mem[start..end].clone_from_slice(&big_fonts);
     }
 
-    pub fn load_rom(&mut self, filename: &String) {
-        load_rom(filename, &mut self.mem);
+    /// Copies `rom` into memory at its load address and jumps to its start
+    /// address, failing if [`Rom::check`] does.
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        rom.check()?;
+        let start = rom.load_address as usize;
+        self.mem[start..start + rom.bytes().len()].copy_from_slice(rom.bytes());
+        self.pc = rom.start_address;
+        self.op_pc = rom.start_address;
+        self.rom_hash = rom.hash().to_string();
+
+        Ok(())
     }
 
-    pub fn cycle(&mut self) {
-        let pc = self.pc as usize;
-        let bytes = (self.mem[pc] as u16) << 8 | self.mem[pc + 1] as u16;
+    /// Copies `rom` into memory at [`START_ADDRESS`].
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        self.load_rom(&Rom::init(rom.to_vec()))
+    }
+
+    /// Restarts the random number generator behind `RND` from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::init(seed);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
 
-        self.pc += 2;
-        match Instruction::parse(bytes) {
-            Ok(inst) => {
-                println!("{:x?}: {:x?} {:?}", pc, bytes, inst);
-                self.execute(inst);
+    /// Hex SHA-1 of the loaded ROM.
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /// Serializes the machine state, stamped with the loaded ROM's hash.
+    ///
+    /// Quirks are configuration rather than state and are not saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Writer::default();
+        state.bytes(&self.mem);
+        state.bytes(&self.v);
+        state.u16(self.i);
+        state.u8(self.dt);
+        state.u8(self.st);
+        state.u16(self.pc);
+        state.u8(self.sp);
+        self.stack.iter().for_each(|&addr| state.u16(addr));
+        state.bytes(&self.rpl);
+        state.u8(self.exited as u8);
+        state.u8(self.planes);
+        state.u8(self.pattern.is_some() as u8);
+        state.bytes(&self.pattern.unwrap_or([0; 16]));
+        state.u8(self.pitch);
+        self.keys.iter().for_each(|&key| state.u8(key as u8));
+        state.u16(self.pixels.width() as u16);
+        state.u16(self.pixels.height() as u16);
+        self.pixels.rows().for_each(|row| state.bytes(row));
+        state.u64(self.rng.state());
+
+        state.seal(&self.rom_hash)
+    }
+
+    /// Restores a state saved by [`Cpu::save_state`] for the same ROM.
+    ///
+    /// The state is checked in full before anything is restored, so on error
+    /// the machine is left as it was. Version 1 states predate the saved
+    /// random number generator and keep the current one.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (version, mut state) = Reader::open(state, &self.rom_hash)?;
+
+        let mem = state.bytes(MEMORY_SIZE)?.to_vec();
+        let v = state.array()?;
+        let i = state.u16()?;
+        let dt = state.u8()?;
+        let st = state.u8()?;
+        let pc = state.u16()?;
+        let sp = state.u8()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = state.u16()?;
+        }
+        let rpl = state.array()?;
+        let exited = state.bool()?;
+        let planes = state.u8()?;
+        let has_pattern = state.bool()?;
+        let pattern = state.array()?;
+        let pitch = state.u8()?;
+        let mut keys = [false; 16];
+        for key in keys.iter_mut() {
+            *key = state.bool()?;
+        }
+        let width = state.u16()? as usize;
+        let height = state.u16()? as usize;
+        let mut pixels = match (width, height) {
+            (WIDTH, HEIGHT) | (HIRES_WIDTH, HIRES_HEIGHT) => Framebuffer::init(width, height),
+            _ => return Err(StateError::Corrupt),
+        };
+        for y in 0..height {
+            for (x, &color) in state.bytes(width)?.iter().enumerate() {
+                if color > 0b11 {
+                    return Err(StateError::Corrupt);
+                }
+                pixels.set(x, y, color);
             }
-            Err(error) => {
-                panic!(
-                    "Failed to execute instruction at {:x?} due to {:?}",
-                    pc, error
-                );
+        }
+        let rng = match version {
+            1 => self.rng,
+            _ => Rng::init(state.u64()?),
+        };
+        state.finish()?;
+        if sp as usize > stack.len() || planes > 0b11 {
+            return Err(StateError::Corrupt);
+        }
+
+        self.mem = mem;
+        self.v = v;
+        self.i = i;
+        self.dt = dt;
+        self.st = st;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack = stack;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.planes = planes;
+        self.pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = pitch;
+        self.keys = keys;
+        self.pixels = pixels;
+        self.rng = rng;
+        self.last_write = None;
+        self.last_read = None;
+        self.should_draw = true;
+
+        Ok(())
+    }
+
+    /// Returns `len` bytes of memory from `start`, or an error naming the first
+    /// address past the end of memory.
+    fn mem_range(&self, start: usize, len: usize) -> Result<Range<usize>, Chip8Error> {
+        let end = start + len;
+        if end > self.mem.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                pc: self.op_pc,
+                opcode: self.opcode,
+                addr: start.max(self.mem.len()),
+            });
+        }
+
+        Ok(start..end)
+    }
+
+    /// Decodes the instruction at `addr`, along with its first word.
+    fn fetch(&self, addr: u16) -> Result<(u16, Instruction), Chip8Error> {
+        let word = |at: usize, opcode: u16| match self.mem.get(at..at + 2) {
+            Some(bytes) => Ok((bytes[0] as u16) << 8 | bytes[1] as u16),
+            None => Err(Chip8Error::MemoryOutOfBounds {
+                pc: addr,
+                opcode,
+                addr: at.max(self.mem.len()),
+            }),
+        };
+
+        let opcode = word(addr as usize, 0)?;
+        let inst = match Instruction::parse(opcode) {
+            // the address of F000 NNNN is in the following word
+            Ok(Instruction::LD_I_LONG(_)) => {
+                Instruction::LD_I_LONG(word(addr as usize + 2, opcode)?)
             }
+            Ok(inst) => inst,
+            Err(_) => return Err(Chip8Error::InvalidOpcode { pc: addr, opcode }),
+        };
+
+        Ok((opcode, inst))
+    }
+
+    /// Decodes the instruction at the program counter without executing it.
+    pub fn peek(&self) -> Result<Instruction, Chip8Error> {
+        self.fetch(self.pc).map(|(_, inst)| inst)
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter.
+    ///
+    /// On error the machine is left as it was just before the faulting
+    /// instruction was executed.
+    ///
+    /// Does nothing once the program has run `EXIT`.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
         }
+
+        let pc = self.pc;
+        let (opcode, inst) = self.fetch(pc)?;
+        self.op_pc = pc;
+        self.opcode = opcode;
+
+        self.pc = self.pc.wrapping_add(inst.size());
+        if let Err(error) = self.execute(inst) {
+            self.pc = pc;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the delay and sound timers down by one. Call this at
+    /// [`FRAME_RATE`], independently of how fast instructions run.
+    pub fn tick_timers(&mut self) {
         if self.dt > 0 {
             self.dt -= 1;
         }
@@ -93,14 +357,137 @@ impl Cpu {
         }
     }
 
-    pub fn execute(&mut self, instruction: Instruction) {
+    /// Runs one 60 Hz frame: `ipf` instructions followed by a timer tick.
+    pub fn run_frame(&mut self, ipf: u32) -> Result<(), Chip8Error> {
+        for _ in 0..ipf {
+            if self.exited {
+                break;
+            }
+            self.cycle()?;
+        }
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    /// Whether the program has stopped itself with the SUPER-CHIP `EXIT`
+    /// instruction.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// The general purpose registers V0 to VF.
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    /// The address register I.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// The program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The stack pointer, which is also the number of return addresses on
+    /// the stack.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The return addresses on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    /// The delay timer.
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    /// The sound timer. The buzzer sounds while it is non-zero.
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    /// The whole of memory.
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// The memory written by the last instruction, if any.
+    pub fn last_write(&self) -> Option<Range<usize>> {
+        self.last_write.clone()
+    }
+
+    /// The memory read as data, such as sprites, by the last instruction.
+    /// Instruction fetches don't count.
+    pub fn last_read(&self) -> Option<Range<usize>> {
+        self.last_read.clone()
+    }
+
+    /// The 16 byte XO-CHIP audio pattern, once the program has loaded one.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.pattern.as_ref()
+    }
+
+    /// The XO-CHIP pitch register. The audio pattern plays back at
+    /// `4000 * 2 ^ ((pitch - 64) / 48)` bits per second.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Skips the next instruction, which is 4 bytes long if it is XO-CHIP's
+    /// `F000 NNNN`.
+    fn skip(&mut self) {
+        let pc = self.pc as usize;
+        let size = match self.mem.get(pc..pc + 2) {
+            Some([0xF0, 0x00]) => 4,
+            _ => 2,
+        };
+
+        self.pc = self.pc.wrapping_add(size);
+    }
+
+    /// Memory addresses from I touched by `SAVE` and `LOAD`, paired with the
+    /// register stored to or loaded from each. The range runs backwards if
+    /// x is greater than y.
+    fn register_range(&self, x: u16, y: u16) -> Result<Vec<(usize, usize)>, Chip8Error> {
+        let (x, y) = (x as usize, y as usize);
+        let count = x.max(y) - x.min(y) + 1;
+        let range = self.mem_range(self.i as usize, count)?;
+
+        let registers: Vec<usize> = match x <= y {
+            true => (x..=y).collect(),
+            false => (y..=x).rev().collect(),
+        };
+
+        Ok(range.zip(registers).collect())
+    }
+
+    /// Executes a single decoded instruction.
+    ///
+    /// Expects the program counter to already point past the instruction.
+    /// Instructions only change state once they are known to succeed.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        self.last_write = None;
+        self.last_read = None;
+
         match instruction {
             Instruction::SYS(_) => (),
             Instruction::CLS => {
-                self.pixels = [[false; WIDTH]; HEIGHT];
+                self.pixels.clear(self.planes);
                 self.should_draw = true;
             }
             Instruction::RET => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow {
+                        pc: self.op_pc,
+                        opcode: self.opcode,
+                    });
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
             }
@@ -108,18 +495,24 @@ impl Cpu {
                 self.pc = addr;
             }
             Instruction::CALL_ADDR(addr) => {
+                if self.sp as usize == self.stack.len() {
+                    return Err(Chip8Error::StackOverflow {
+                        pc: self.op_pc,
+                        opcode: self.opcode,
+                    });
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = addr;
             }
             Instruction::SE_BYTE(addr, byte) => {
                 if self.v[addr as usize] == byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::SNE_BYTE(addr, byte) => {
                 if self.v[addr as usize] != byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::SE(addr_x, addr_y) => {
@@ -127,7 +520,7 @@ impl Cpu {
                 let vy = self.v[addr_y as usize];
 
                 if vx == vy {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::LD_BYTE(addr, byte) => {
@@ -145,18 +538,27 @@ impl Cpu {
                 let y = addr_y as usize;
 
                 self.v[x] |= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xf] = 0;
+                }
             }
             Instruction::AND(addr_x, addr_y) => {
                 let x = addr_x as usize;
                 let y = addr_y as usize;
 
                 self.v[x] &= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xf] = 0;
+                }
             }
             Instruction::XOR(addr_x, addr_y) => {
                 let x = addr_x as usize;
                 let y = addr_y as usize;
 
                 self.v[x] ^= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xf] = 0;
+                }
             }
             Instruction::ADD(addr_x, addr_y) => {
                 let x = addr_x as usize;
@@ -176,11 +578,16 @@ impl Cpu {
                 };
                 self.v[x] = self.v[x].wrapping_sub(self.v[y]);
             }
-            Instruction::SHR(addr) => {
-                let x = addr as usize;
+            Instruction::SHR(addr_x, addr_y) => {
+                let x = addr_x as usize;
+                let y = match self.quirks.shift_vx {
+                    true => x,
+                    false => addr_y as usize,
+                };
 
-                self.v[0xf] = self.v[x] & 0x01;
-                self.v[x] >>= 1;
+                let flag = self.v[y] & 0x01;
+                self.v[x] = self.v[y] >> 1;
+                self.v[0xf] = flag;
             }
             Instruction::SUBN(addr_x, addr_y) => {
                 let x = addr_x as usize;
@@ -192,72 +599,107 @@ impl Cpu {
                 };
                 self.v[x] = self.v[y].wrapping_sub(self.v[x]);
             }
-            Instruction::SHL(addr) => {
-                let x = addr as usize;
+            Instruction::SHL(addr_x, addr_y) => {
+                let x = addr_x as usize;
+                let y = match self.quirks.shift_vx {
+                    true => x,
+                    false => addr_y as usize,
+                };
 
-                self.v[0xf] = (self.v[x] & 0x80) >> 7;
-                self.v[x] <<= 1;
+                let flag = (self.v[y] & 0x80) >> 7;
+                self.v[x] = self.v[y] << 1;
+                self.v[0xf] = flag;
             }
             Instruction::SNE(addr_x, addr_y) => {
                 let x = addr_x as usize;
                 let y = addr_y as usize;
 
                 if self.v[x] != self.v[y] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::LD_I(addr) => {
                 self.i = addr;
             }
             Instruction::JP_V0(addr) => {
-                self.pc = addr + self.v[0] as u16;
+                let x = match self.quirks.jump_vx {
+                    true => (addr >> 8) as usize,
+                    false => 0,
+                };
+
+                self.pc = addr + self.v[x] as u16;
             }
             Instruction::RND_BYTE(addr, byte) => {
                 let x = addr as usize;
 
-                let mut rng = rand::thread_rng();
-                let rand: u8 = rng.gen();
-
-                self.v[x] = rand & byte;
+                self.v[x] = self.rng.next_u8() & byte;
             }
             Instruction::DRW(addr_x, addr_y, n) => {
-                let vx = self.v[addr_x as usize] as usize % WIDTH;
-                let vy = self.v[addr_y as usize] as usize % HEIGHT;
-
-                let start = self.i as usize;
-                let end = start + n as usize;
+                let width = self.pixels.width();
+                let height = self.pixels.height();
+                let vx = self.v[addr_x as usize] as usize % width;
+                let vy = self.v[addr_y as usize] as usize % height;
+
+                // DRW Vx, Vy, 0 draws a 16x16 sprite stored as 2 bytes per row
+                let (cols, rows) = match n {
+                    0 => (16, 16),
+                    n => (8, n as usize),
+                };
+                let bytes_per_row = cols / 8;
+                let sprite_len = rows * bytes_per_row;
 
-                let sprite = &self.mem[start..end];
+                // XO-CHIP draws one sprite per selected plane, stored back to back
+                let planes: Vec<u8> = [0b01, 0b10]
+                    .iter()
+                    .copied()
+                    .filter(|plane| self.planes & plane != 0)
+                    .collect();
+                let sprites = self.mem_range(self.i as usize, sprite_len * planes.len())?;
 
                 self.v[0xf] = 0;
-                for (row, sprite_byte) in sprite.iter().enumerate() {
-                    for col in 0..8 {
-                        let sprite_pixel = (sprite_byte >> (7 - col)) & 1;
-                        let screen_pixel = self.pixels[vy + row][vx + col] as u8;
-                        let old_pixel = screen_pixel;
-
-                        if screen_pixel == 1 && sprite_pixel == 1 {
-                            self.v[0xf] = 1;
+                self.last_read = Some(sprites.clone());
+                for (plane, sprite) in planes.into_iter().zip(self.mem[sprites].chunks(sprite_len))
+                {
+                    for (row, sprite_row) in sprite.chunks(bytes_per_row).enumerate() {
+                        let y = vy + row;
+                        if y >= height && self.quirks.clip_sprites {
+                            break;
                         }
-                        self.pixels[vy + row][vx + col] = (screen_pixel ^ sprite_pixel) != 0;
-                        if self.pixels[vy + row][vx + col] != (old_pixel == 1) {
+                        let y = y % height;
+
+                        for col in 0..cols {
+                            let x = vx + col;
+                            if x >= width && self.quirks.clip_sprites {
+                                break;
+                            }
+                            let x = x % width;
+
+                            let sprite_byte = sprite_row[col / 8];
+                            if (sprite_byte >> (7 - col % 8)) & 1 == 0 {
+                                continue;
+                            }
+
+                            if self.pixels.toggle(x, y, plane) {
+                                self.v[0xf] = 1;
+                            }
                             self.should_draw = true;
                         }
                     }
                 }
             }
             Instruction::SKP(addr) => {
-                let key = self.v[addr as usize] as usize;
+                // only the low nibble reaches the keypad
+                let key = (self.v[addr as usize] & 0x0F) as usize;
 
-                if self.keys[key] == true {
-                    self.pc += 2;
+                if self.keys[key] {
+                    self.skip();
                 }
             }
             Instruction::SKNP(addr) => {
-                let key = self.v[addr as usize] as usize;
+                let key = (self.v[addr as usize] & 0x0F) as usize;
 
-                if self.keys[key] == false {
-                    self.pc += 2;
+                if !self.keys[key] {
+                    self.skip();
                 }
             }
             Instruction::LD_DT(addr) => {
@@ -268,7 +710,7 @@ impl Cpu {
                 let mut wait = true;
 
                 for (key, pressed) in self.keys.iter().enumerate() {
-                    if *pressed == true {
+                    if *pressed {
                         self.v[x] = key as u8;
                         wait = false;
                         break;
@@ -276,7 +718,7 @@ impl Cpu {
                 }
 
                 if wait {
-                    self.pc -= 2;
+                    self.pc = self.pc.wrapping_sub(2);
                 }
             }
             Instruction::LD_DT_SET(addr) => {
@@ -295,30 +737,108 @@ impl Cpu {
             }
             Instruction::LD_B(addr) => {
                 let mut vx = self.v[addr as usize] as usize;
+                let bcd = self.mem_range(self.i as usize, 3)?;
 
-                self.mem[(self.i + 2) as usize] = (vx % 10) as u8;
+                self.mem[bcd.start + 2] = (vx % 10) as u8;
                 vx /= 10;
 
-                self.mem[(self.i + 1) as usize] = (vx % 10) as u8;
+                self.mem[bcd.start + 1] = (vx % 10) as u8;
                 vx /= 10;
 
-                self.mem[(self.i) as usize] = (vx % 10) as u8;
+                self.mem[bcd.start] = (vx % 10) as u8;
+                self.last_write = Some(bcd);
             }
             Instruction::LD_STORE_I(addr) => {
                 let vx = addr as usize + 1;
-                let start = self.i as usize;
-                let end = start + vx;
+                let range = self.mem_range(self.i as usize, vx)?;
 
-                self.mem[start..end].clone_from_slice(&self.v[0..vx]);
+                self.mem[range.clone()].clone_from_slice(&self.v[0..vx]);
+                self.last_write = Some(range);
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(vx as u16);
+                }
             }
             Instruction::LD_READ_I(addr) => {
                 let vx = addr as usize + 1;
-                let start = self.i as usize;
-                let end = start + vx;
+                let range = self.mem_range(self.i as usize, vx)?;
+
+                self.v[0..vx].clone_from_slice(&self.mem[range.clone()]);
+                self.last_read = Some(range);
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(vx as u16);
+                }
+            }
+            Instruction::SCD(n) => {
+                self.pixels.scroll_down(n as usize, self.planes);
+                self.should_draw = true;
+            }
+            Instruction::SCR => {
+                self.pixels.scroll_right(4, self.planes);
+                self.should_draw = true;
+            }
+            Instruction::SCL => {
+                self.pixels.scroll_left(4, self.planes);
+                self.should_draw = true;
+            }
+            Instruction::EXIT => {
+                self.exited = true;
+            }
+            Instruction::LOW => {
+                self.pixels.resize(WIDTH, HEIGHT);
+                self.should_draw = true;
+            }
+            Instruction::HIGH => {
+                self.pixels.resize(HIRES_WIDTH, HIRES_HEIGHT);
+                self.should_draw = true;
+            }
+            Instruction::LD_HF(addr) => {
+                let vx = (self.v[addr as usize] & 0x0F) as usize;
+                self.i = BIG_FONT_START_ADDRESS + (vx * 10) as u16;
+            }
+            Instruction::LD_STORE_R(addr) => {
+                let vx = addr as usize + 1;
 
-                self.v[0..vx].clone_from_slice(&self.mem[start..end]);
+                self.rpl[0..vx].clone_from_slice(&self.v[0..vx]);
+            }
+            Instruction::LD_READ_R(addr) => {
+                let vx = addr as usize + 1;
+
+                self.v[0..vx].clone_from_slice(&self.rpl[0..vx]);
+            }
+            Instruction::SAVE(addr_x, addr_y) => {
+                let registers = self.register_range(addr_x, addr_y)?;
+                for &(addr, reg) in registers.iter() {
+                    self.mem[addr] = self.v[reg];
+                }
+                self.last_write = Some(registers[0].0..registers[0].0 + registers.len());
+            }
+            Instruction::LOAD(addr_x, addr_y) => {
+                let registers = self.register_range(addr_x, addr_y)?;
+                for &(addr, reg) in registers.iter() {
+                    self.v[reg] = self.mem[addr];
+                }
+                self.last_read = Some(registers[0].0..registers[0].0 + registers.len());
+            }
+            Instruction::LD_I_LONG(addr) => {
+                self.i = addr;
+            }
+            Instruction::PLANE(n) => {
+                self.planes = n & 0b11;
+            }
+            Instruction::AUDIO => {
+                let range = self.mem_range(self.i as usize, 16)?;
+                let mut pattern = [0; 16];
+                pattern.clone_from_slice(&self.mem[range.clone()]);
+                self.last_read = Some(range);
+
+                self.pattern = Some(pattern);
+            }
+            Instruction::PITCH(addr) => {
+                self.pitch = self.v[addr as usize];
             }
         }
+
+        Ok(())
     }
 }
 
@@ -328,20 +848,21 @@ mod tests {
 
     #[test]
     fn test_cls() {
-        let mut cpu = Cpu::init();
-        cpu.pixels = [[true; WIDTH]; HEIGHT];
-        cpu.execute(Instruction::CLS);
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.pixels.set(0, 0, 1);
+        cpu.pixels.set(WIDTH - 1, HEIGHT - 1, 1);
+        cpu.execute(Instruction::CLS).unwrap();
 
-        assert_eq!(cpu.pixels, [[false; WIDTH]; HEIGHT]);
+        assert_eq!(cpu.pixels, Framebuffer::init(WIDTH, HEIGHT));
     }
 
     #[test]
     fn test_ret() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.stack[0] = 0x123;
-        cpu.pc = 0xFFF;
+        cpu.pc = 0xFFFF;
         cpu.sp = 1;
-        cpu.execute(Instruction::RET);
+        cpu.execute(Instruction::RET).unwrap();
 
         assert_eq!(cpu.sp, 0);
         assert_eq!(cpu.pc, 0x123);
@@ -349,18 +870,18 @@ mod tests {
 
     #[test]
     fn test_jp_addr() {
-        let mut cpu = Cpu::init();
-        cpu.execute(Instruction::JP_ADDR(0x0123));
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::JP_ADDR(0x0123)).unwrap();
 
         assert_eq!(cpu.pc, 0x0123);
     }
 
     #[test]
     fn test_call_addr() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.sp = 0;
         cpu.pc = 0x0ABC;
-        cpu.execute(Instruction::CALL_ADDR(0x0123));
+        cpu.execute(Instruction::CALL_ADDR(0x0123)).unwrap();
 
         assert_eq!(cpu.stack[0], 0xABC);
         assert_eq!(cpu.sp, 1);
@@ -369,83 +890,83 @@ mod tests {
 
     #[test]
     fn test_se_byte_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 0x12;
-        cpu.execute(Instruction::SE_BYTE(1, 0x012));
+        cpu.execute(Instruction::SE_BYTE(1, 0x012)).unwrap();
 
         assert_eq!(cpu.pc, 2 + START_ADDRESS);
     }
 
     #[test]
     fn test_se_byte_not_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 0x12;
-        cpu.execute(Instruction::SE_BYTE(1, 0));
+        cpu.execute(Instruction::SE_BYTE(1, 0)).unwrap();
 
-        assert_eq!(cpu.pc, 0 + START_ADDRESS);
+        assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_sne_byte_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 0x12;
-        cpu.execute(Instruction::SNE_BYTE(1, 0x12));
+        cpu.execute(Instruction::SNE_BYTE(1, 0x12)).unwrap();
 
-        assert_eq!(cpu.pc, 0 + START_ADDRESS);
+        assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_sne_byte_not_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 0x12;
-        cpu.execute(Instruction::SNE_BYTE(0x0001, 0x00));
+        cpu.execute(Instruction::SNE_BYTE(0x0001, 0x00)).unwrap();
 
         assert_eq!(cpu.pc, 2 + START_ADDRESS);
     }
 
     #[test]
     fn test_se_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x12;
         cpu.v[1] = 0x12;
-        cpu.execute(Instruction::SE(0, 1));
+        cpu.execute(Instruction::SE(0, 1)).unwrap();
 
         assert_eq!(cpu.pc, 2 + START_ADDRESS);
     }
 
     #[test]
     fn test_se_not_equal() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x12;
         cpu.v[1] = 0x34;
-        cpu.execute(Instruction::SE(0, 1));
+        cpu.execute(Instruction::SE(0, 1)).unwrap();
 
-        assert_eq!(cpu.pc, 0 + START_ADDRESS);
+        assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_ld_byte() {
-        let mut cpu = Cpu::init();
-        cpu.execute(Instruction::LD_BYTE(0, 0x12));
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::LD_BYTE(0, 0x12)).unwrap();
 
         assert_eq!(cpu.v[0], 0x12);
     }
 
     #[test]
     fn test_add_byte() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x01;
-        cpu.execute(Instruction::ADD_BYTE(0, 0x01));
+        cpu.execute(Instruction::ADD_BYTE(0, 0x01)).unwrap();
 
         assert_eq!(cpu.v[0], 0x02);
     }
 
     #[test]
     fn test_ld() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x01;
         cpu.v[1] = 0x02;
-        cpu.execute(Instruction::LD(0, 1));
+        cpu.execute(Instruction::LD(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0x02);
         assert_eq!(cpu.v[1], 0x02);
@@ -453,40 +974,40 @@ mod tests {
 
     #[test]
     fn test_or() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x01;
         cpu.v[1] = 0x10;
-        cpu.execute(Instruction::OR(0, 1));
+        cpu.execute(Instruction::OR(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0x11);
     }
 
     #[test]
     fn test_and() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x01;
         cpu.v[1] = 0x11;
-        cpu.execute(Instruction::AND(0, 1));
+        cpu.execute(Instruction::AND(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0x01);
     }
 
     #[test]
     fn test_xor() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x11;
         cpu.v[1] = 0x10;
-        cpu.execute(Instruction::XOR(0, 1));
+        cpu.execute(Instruction::XOR(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0x01);
     }
 
     #[test]
     fn test_add_no_carry() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0xFE;
         cpu.v[1] = 0x01;
-        cpu.execute(Instruction::ADD(0, 1));
+        cpu.execute(Instruction::ADD(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0xFF);
         assert_eq!(cpu.v[0xf], 0);
@@ -494,10 +1015,10 @@ mod tests {
 
     #[test]
     fn test_add_carry() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0xFF;
         cpu.v[1] = 0x01;
-        cpu.execute(Instruction::ADD(0, 1));
+        cpu.execute(Instruction::ADD(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0x00);
         assert_eq!(cpu.v[0xf], 1);
@@ -505,10 +1026,10 @@ mod tests {
 
     #[test]
     fn test_sub_vx_eq_vy() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::SUB(0, 1));
+        cpu.execute(Instruction::SUB(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0);
         assert_eq!(cpu.v[1], 1);
@@ -517,10 +1038,10 @@ mod tests {
 
     #[test]
     fn test_sub_vx_lt_vy() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 2;
-        cpu.execute(Instruction::SUB(0, 1));
+        cpu.execute(Instruction::SUB(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 255);
         assert_eq!(cpu.v[1], 2);
@@ -529,10 +1050,10 @@ mod tests {
 
     #[test]
     fn test_sub_vx_gt_vy() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 2;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::SUB(0, 1));
+        cpu.execute(Instruction::SUB(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 1);
         assert_eq!(cpu.v[1], 1);
@@ -541,9 +1062,9 @@ mod tests {
 
     #[test]
     fn test_shr_set_0() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0x02;
-        cpu.execute(Instruction::SHR(0));
+        cpu.execute(Instruction::SHR(0, 0)).unwrap();
 
         assert_eq!(cpu.v[0], 0x01);
         assert_eq!(cpu.v[0xf], 0);
@@ -551,9 +1072,9 @@ mod tests {
 
     #[test]
     fn test_shr_set_1() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
-        cpu.execute(Instruction::SHR(0));
+        cpu.execute(Instruction::SHR(0, 0)).unwrap();
 
         assert_eq!(cpu.v[0], 0x00);
         assert_eq!(cpu.v[0xf], 1);
@@ -561,10 +1082,10 @@ mod tests {
 
     #[test]
     fn test_subn_vy_eq_vx() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::SUBN(0, 1));
+        cpu.execute(Instruction::SUBN(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 0);
         assert_eq!(cpu.v[1], 1);
@@ -573,10 +1094,10 @@ mod tests {
 
     #[test]
     fn test_subn_vy_lt_vx() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 2;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::SUBN(0, 1));
+        cpu.execute(Instruction::SUBN(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 255);
         assert_eq!(cpu.v[1], 1);
@@ -585,10 +1106,10 @@ mod tests {
 
     #[test]
     fn test_subn_vy_gt_vx() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 2;
-        cpu.execute(Instruction::SUBN(0, 1));
+        cpu.execute(Instruction::SUBN(0, 1)).unwrap();
 
         assert_eq!(cpu.v[0], 1);
         assert_eq!(cpu.v[1], 2);
@@ -597,9 +1118,9 @@ mod tests {
 
     #[test]
     fn test_shl_set_0() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
-        cpu.execute(Instruction::SHL(0));
+        cpu.execute(Instruction::SHL(0, 0)).unwrap();
 
         assert_eq!(cpu.v[0], 2);
         assert_eq!(cpu.v[0xf], 0);
@@ -607,9 +1128,9 @@ mod tests {
 
     #[test]
     fn test_shl_set_1() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 255;
-        cpu.execute(Instruction::SHL(0));
+        cpu.execute(Instruction::SHL(0, 0)).unwrap();
 
         assert_eq!(cpu.v[0], 254);
         assert_eq!(cpu.v[0xf], 1);
@@ -617,131 +1138,131 @@ mod tests {
 
     #[test]
     fn test_sne_eq() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::SNE(0, 1));
+        cpu.execute(Instruction::SNE(0, 1)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_sne_neq() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.v[1] = 2;
-        cpu.execute(Instruction::SNE(0, 1));
+        cpu.execute(Instruction::SNE(0, 1)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS + 2);
     }
 
     #[test]
     fn test_ld_i() {
-        let mut cpu = Cpu::init();
-        cpu.execute(Instruction::LD_I(0x0FFF));
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::LD_I(0x0FFF)).unwrap();
 
         assert_eq!(cpu.i, 0x0FFF);
     }
 
     #[test]
     fn test_jp_v0() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
-        cpu.execute(Instruction::JP_V0(1));
+        cpu.execute(Instruction::JP_V0(1)).unwrap();
 
         assert_eq!(cpu.pc, 2);
     }
 
     #[test]
     fn test_rnd() {
-        let mut cpu = Cpu::init();
-        cpu.execute(Instruction::RND_BYTE(0, 0xFF));
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::RND_BYTE(0, 0xFF)).unwrap();
 
         assert_ne!(cpu.v[0], 0);
     }
 
     #[test]
     fn test_drw_no_collision() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.i = FONT_START_ADDRESS;
         cpu.v[0] = 0;
         cpu.v[1] = 0;
-        cpu.execute(Instruction::DRW(0, 1, 1));
+        cpu.execute(Instruction::DRW(0, 1, 1)).unwrap();
 
-        assert_eq!(cpu.pixels[0][0], true);
+        assert_eq!(cpu.pixels.get(0, 0), 1);
         assert_eq!(cpu.v[0xf], 0);
     }
 
     #[test]
     fn test_drw_collision() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.i = FONT_START_ADDRESS;
         cpu.v[0] = 0;
         cpu.v[1] = 0;
-        cpu.execute(Instruction::DRW(0, 1, 1));
-        cpu.execute(Instruction::DRW(0, 1, 1));
+        cpu.execute(Instruction::DRW(0, 1, 1)).unwrap();
+        cpu.execute(Instruction::DRW(0, 1, 1)).unwrap();
 
-        assert_eq!(cpu.pixels[0][0], false);
+        assert_eq!(cpu.pixels.get(0, 0), 0);
         assert_eq!(cpu.v[0xf], 1);
     }
 
     #[test]
     fn test_skp_pressed() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.keys[1] = true;
 
-        cpu.execute(Instruction::SKP(0));
+        cpu.execute(Instruction::SKP(0)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS + 2);
     }
 
     #[test]
     fn test_skp_not_pressed() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
 
-        cpu.execute(Instruction::SKP(0));
+        cpu.execute(Instruction::SKP(0)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_sknp_pressed() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
         cpu.keys[1] = true;
 
-        cpu.execute(Instruction::SKNP(0));
+        cpu.execute(Instruction::SKNP(0)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS);
     }
 
     #[test]
     fn test_sknp_not_pressed() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 1;
 
-        cpu.execute(Instruction::SKNP(0));
+        cpu.execute(Instruction::SKNP(0)).unwrap();
 
         assert_eq!(cpu.pc, START_ADDRESS + 2);
     }
 
     #[test]
     fn test_ld_dt() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.dt = 123;
-        cpu.execute(Instruction::LD_DT(1));
+        cpu.execute(Instruction::LD_DT(1)).unwrap();
 
         assert_eq!(cpu.v[1], 123);
     }
 
     #[test]
     fn test_ld_key_pressed() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.keys[1] = true;
         cpu.v[0] = 1;
-        cpu.execute(Instruction::LD_KEY(0));
+        cpu.execute(Instruction::LD_KEY(0)).unwrap();
 
         assert_eq!(cpu.v[0], 1);
         assert_eq!(cpu.pc, START_ADDRESS);
@@ -749,8 +1270,8 @@ mod tests {
 
     #[test]
     fn test_ld_key_not_pressed() {
-        let mut cpu = Cpu::init();
-        cpu.execute(Instruction::LD_KEY(0));
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::LD_KEY(0)).unwrap();
 
         assert_eq!(cpu.v[0], 0);
         assert_eq!(cpu.pc, START_ADDRESS - 2);
@@ -758,50 +1279,50 @@ mod tests {
 
     #[test]
     fn test_ld_dt_set() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 123;
-        cpu.execute(Instruction::LD_DT_SET(1));
+        cpu.execute(Instruction::LD_DT_SET(1)).unwrap();
 
         assert_eq!(cpu.dt, 123);
     }
 
     #[test]
     fn test_ld_st_set() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[1] = 123;
-        cpu.execute(Instruction::LD_ST_SET(1));
+        cpu.execute(Instruction::LD_ST_SET(1)).unwrap();
 
         assert_eq!(cpu.st, 123);
     }
 
     #[test]
     fn test_add_i() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.i = 1;
         cpu.v[1] = 1;
-        cpu.execute(Instruction::ADD_I(1));
+        cpu.execute(Instruction::ADD_I(1)).unwrap();
 
         assert_eq!(cpu.i, 2);
     }
 
     #[test]
     fn test_ld_f() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
 
-        cpu.execute(Instruction::LD_F(0));
+        cpu.execute(Instruction::LD_F(0)).unwrap();
         assert_eq!(cpu.i, FONT_START_ADDRESS);
 
         cpu.v[0] = 1;
-        cpu.execute(Instruction::LD_F(0));
+        cpu.execute(Instruction::LD_F(0)).unwrap();
         assert_eq!(cpu.i, FONT_START_ADDRESS + 5);
     }
 
     #[test]
     fn test_ld_b() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 123;
 
-        cpu.execute(Instruction::LD_B(0));
+        cpu.execute(Instruction::LD_B(0)).unwrap();
 
         assert_eq!(cpu.mem[cpu.i as usize], 1);
         assert_eq!(cpu.mem[(cpu.i + 1) as usize], 2);
@@ -810,12 +1331,12 @@ mod tests {
 
     #[test]
     fn test_ld_store_i() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
         cpu.v[0] = 0;
         cpu.v[1] = 1;
         cpu.v[2] = 2;
 
-        cpu.execute(Instruction::LD_STORE_I(2));
+        cpu.execute(Instruction::LD_STORE_I(2)).unwrap();
 
         assert_eq!(cpu.mem[cpu.i as usize], 0);
         assert_eq!(cpu.mem[(cpu.i + 1) as usize], 1);
@@ -824,16 +1345,596 @@ mod tests {
 
     #[test]
     fn test_ld_read_i() {
-        let mut cpu = Cpu::init();
+        let mut cpu = Cpu::init(Quirks::default());
 
         cpu.mem[(cpu.i) as usize] = 0;
         cpu.mem[(cpu.i + 1) as usize] = 1;
         cpu.mem[(cpu.i + 2) as usize] = 2;
 
-        cpu.execute(Instruction::LD_READ_I(2));
+        cpu.execute(Instruction::LD_READ_I(2)).unwrap();
 
         assert_eq!(cpu.v[0], 0);
         assert_eq!(cpu.v[1], 1);
         assert_eq!(cpu.v[2], 2);
     }
+
+    #[test]
+    fn test_cycle_invalid_opcode() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.mem[0x200] = 0xFF;
+        cpu.mem[0x201] = 0xFF;
+
+        assert_eq!(
+            cpu.cycle(),
+            Err(Chip8Error::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0xFFFF
+            })
+        );
+        assert_eq!(cpu.pc, START_ADDRESS);
+    }
+
+    #[test]
+    fn test_cycle_stack_overflow() {
+        let mut cpu = Cpu::init(Quirks::default());
+        // CALL 0x200, calling itself forever
+        cpu.mem[0x200] = 0x22;
+        cpu.mem[0x201] = 0x00;
+
+        for _ in 0..16 {
+            cpu.cycle().unwrap();
+        }
+
+        assert_eq!(
+            cpu.cycle(),
+            Err(Chip8Error::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+        assert_eq!(cpu.sp, 16);
+    }
+
+    #[test]
+    fn test_ret_stack_underflow() {
+        let mut cpu = Cpu::init(Quirks::default());
+
+        assert!(matches!(
+            cpu.execute(Instruction::RET),
+            Err(Chip8Error::StackUnderflow { .. })
+        ));
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    fn test_cycle_pc_out_of_bounds() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.pc = 0xFFFF;
+
+        assert!(matches!(
+            cpu.cycle(),
+            Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_drw_clips_at_edge() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = FONT_START_ADDRESS;
+        cpu.v[0] = (WIDTH - 2) as u8;
+        cpu.v[1] = (HEIGHT - 1) as u8;
+        cpu.execute(Instruction::DRW(0, 1, 5)).unwrap();
+
+        assert_eq!(cpu.pixels.get(WIDTH - 1, HEIGHT - 1), 1);
+        assert_eq!(cpu.pixels.get(0, 0), 0);
+    }
+
+    #[test]
+    fn test_drw_out_of_bounds() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = 0xFFFE;
+
+        assert!(matches!(
+            cpu.execute(Instruction::DRW(0, 1, 5)),
+            Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_ld_b_out_of_bounds() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = 0xFFFE;
+
+        assert!(matches!(
+            cpu.execute(Instruction::LD_B(0)),
+            Err(Chip8Error::MemoryOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ld_store_i_out_of_bounds() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = 0xFFFF;
+
+        assert!(matches!(
+            cpu.execute(Instruction::LD_STORE_I(1)),
+            Err(Chip8Error::MemoryOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        let mut cpu = Cpu::init(Quirks::vip());
+        cpu.v[0xf] = 1;
+        cpu.execute(Instruction::OR(0, 1)).unwrap();
+        assert_eq!(cpu.v[0xf], 0);
+
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.v[0xf] = 1;
+        cpu.execute(Instruction::OR(0, 1)).unwrap();
+        assert_eq!(cpu.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_increment_i_quirk() {
+        let mut cpu = Cpu::init(Quirks::vip());
+        cpu.i = 0x300;
+        cpu.execute(Instruction::LD_STORE_I(2)).unwrap();
+        assert_eq!(cpu.i, 0x303);
+        cpu.execute(Instruction::LD_READ_I(1)).unwrap();
+        assert_eq!(cpu.i, 0x305);
+
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.i = 0x300;
+        cpu.execute(Instruction::LD_STORE_I(2)).unwrap();
+        assert_eq!(cpu.i, 0x300);
+    }
+
+    #[test]
+    fn test_shift_vx_quirk() {
+        let mut cpu = Cpu::init(Quirks::vip());
+        cpu.v[0] = 0x10;
+        cpu.v[1] = 0x03;
+        cpu.execute(Instruction::SHR(0, 1)).unwrap();
+        assert_eq!(cpu.v[0], 0x01);
+        assert_eq!(cpu.v[0xf], 1);
+
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.v[0] = 0x10;
+        cpu.v[1] = 0x03;
+        cpu.execute(Instruction::SHL(0, 1)).unwrap();
+        assert_eq!(cpu.v[0], 0x20);
+        assert_eq!(cpu.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_shift_flag_overwrites_vf() {
+        let mut cpu = Cpu::init(Quirks::vip());
+        cpu.v[0xf] = 0x81;
+        cpu.execute(Instruction::SHL(0xf, 0xf)).unwrap();
+
+        assert_eq!(cpu.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_jump_vx_quirk() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.v[0] = 1;
+        cpu.v[3] = 2;
+        cpu.execute(Instruction::JP_V0(0x0300)).unwrap();
+
+        assert_eq!(cpu.pc, 0x302);
+    }
+
+    #[test]
+    fn test_clip_sprites_quirk() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        cpu.i = FONT_START_ADDRESS;
+        cpu.v[0] = (WIDTH - 2) as u8;
+        cpu.v[1] = (HEIGHT - 1) as u8;
+        cpu.execute(Instruction::DRW(0, 1, 5)).unwrap();
+
+        assert_eq!(cpu.pixels.get(WIDTH - 1, HEIGHT - 1), 1);
+        assert_eq!(cpu.pixels.get(0, HEIGHT - 1), 1);
+        assert_eq!(cpu.pixels.get(WIDTH - 2, 0), 1);
+    }
+
+    #[test]
+    fn test_cycle_does_not_tick_timers() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.dt = 2;
+        cpu.st = 2;
+        // LD V0, 0
+        cpu.mem[0x200] = 0x60;
+        cpu.cycle().unwrap();
+
+        assert_eq!(cpu.dt, 2);
+        assert_eq!(cpu.st, 2);
+    }
+
+    #[test]
+    fn test_tick_timers() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.dt = 1;
+        cpu.st = 2;
+
+        cpu.tick_timers();
+        assert_eq!(cpu.dt, 0);
+        assert_eq!(cpu.st, 1);
+
+        cpu.tick_timers();
+        assert_eq!(cpu.dt, 0);
+        assert_eq!(cpu.st, 0);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.dt = 5;
+        // ADD V0, 1 repeated
+        for addr in (0x200..0x220).step_by(2) {
+            cpu.mem[addr] = 0x70;
+            cpu.mem[addr + 1] = 0x01;
+        }
+
+        cpu.run_frame(10).unwrap();
+
+        assert_eq!(cpu.v[0], 10);
+        assert_eq!(cpu.pc, START_ADDRESS + 20);
+        assert_eq!(cpu.dt, 4);
+    }
+
+    #[test]
+    fn test_high_low() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.execute(Instruction::HIGH).unwrap();
+        assert_eq!(cpu.pixels.width(), HIRES_WIDTH);
+        assert_eq!(cpu.pixels.height(), HIRES_HEIGHT);
+
+        cpu.execute(Instruction::LOW).unwrap();
+        assert_eq!(cpu.pixels.width(), WIDTH);
+        assert_eq!(cpu.pixels.height(), HEIGHT);
+    }
+
+    #[test]
+    fn test_drw_hires() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.execute(Instruction::HIGH).unwrap();
+        cpu.i = FONT_START_ADDRESS;
+        cpu.v[0] = 100;
+        cpu.v[1] = 60;
+        cpu.execute(Instruction::DRW(0, 1, 1)).unwrap();
+
+        assert_eq!(cpu.pixels.get(100, 60), 1);
+        assert_eq!(cpu.pixels.get(103, 60), 1);
+        assert_eq!(cpu.pixels.get(104, 60), 0);
+    }
+
+    #[test]
+    fn test_drw_16x16() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.i = 0x300;
+        for addr in 0x300..0x320 {
+            cpu.mem[addr] = 0xFF;
+        }
+        cpu.execute(Instruction::DRW(0, 1, 0)).unwrap();
+
+        assert_eq!(cpu.pixels.get(15, 15), 1);
+        assert_eq!(cpu.pixels.get(16, 15), 0);
+        assert_eq!(cpu.pixels.get(15, 16), 0);
+        assert_eq!(cpu.v[0xf], 0);
+
+        cpu.execute(Instruction::DRW(0, 1, 0)).unwrap();
+        assert_eq!(cpu.pixels.get(15, 15), 0);
+        assert_eq!(cpu.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.pixels.set(10, 10, 1);
+
+        cpu.execute(Instruction::SCD(3)).unwrap();
+        assert_eq!(cpu.pixels.get(10, 13), 1);
+
+        cpu.execute(Instruction::SCR).unwrap();
+        assert_eq!(cpu.pixels.get(14, 13), 1);
+
+        cpu.execute(Instruction::SCL).unwrap();
+        assert_eq!(cpu.pixels.get(10, 13), 1);
+        assert_eq!(cpu.pixels.get(14, 13), 0);
+    }
+
+    #[test]
+    fn test_ld_hf() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.v[0] = 2;
+        cpu.execute(Instruction::LD_HF(0)).unwrap();
+
+        assert_eq!(cpu.i, BIG_FONT_START_ADDRESS + 20);
+        assert_eq!(cpu.mem[cpu.i as usize], 0xFF);
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        cpu.v[0] = 1;
+        cpu.v[1] = 2;
+        cpu.execute(Instruction::LD_STORE_R(1)).unwrap();
+        cpu.v[0] = 0;
+        cpu.v[1] = 0;
+        cpu.execute(Instruction::LD_READ_R(1)).unwrap();
+
+        assert_eq!(cpu.v[0], 1);
+        assert_eq!(cpu.v[1], 2);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut cpu = Cpu::init(Quirks::schip());
+        // EXIT
+        cpu.mem[0x200] = 0x00;
+        cpu.mem[0x201] = 0xFD;
+        cpu.run_frame(10).unwrap();
+
+        assert!(cpu.exited());
+        assert_eq!(cpu.pc, START_ADDRESS + 2);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        cpu.i = 0x300;
+        cpu.v[1] = 1;
+        cpu.v[2] = 2;
+        cpu.v[3] = 3;
+        cpu.execute(Instruction::SAVE(1, 3)).unwrap();
+
+        assert_eq!(cpu.mem[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.i, 0x300);
+
+        cpu.execute(Instruction::LOAD(6, 4)).unwrap();
+        assert_eq!(cpu.v[6], 1);
+        assert_eq!(cpu.v[5], 2);
+        assert_eq!(cpu.v[4], 3);
+    }
+
+    #[test]
+    fn test_ld_i_long() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        cpu.mem[0x200..0x204].clone_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        cpu.cycle().unwrap();
+
+        assert_eq!(cpu.i, 0xABCD);
+        assert_eq!(cpu.pc, START_ADDRESS + 4);
+    }
+
+    #[test]
+    fn test_skip_over_ld_i_long() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        // SE V0, 0 followed by F000 NNNN
+        cpu.mem[0x200..0x206].clone_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        cpu.cycle().unwrap();
+
+        assert_eq!(cpu.pc, START_ADDRESS + 6);
+    }
+
+    #[test]
+    fn test_memory_past_4k() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        cpu.i = 0xF000;
+        cpu.v[0] = 7;
+        cpu.execute(Instruction::LD_STORE_I(0)).unwrap();
+
+        assert_eq!(cpu.mem[0xF000], 7);
+    }
+
+    #[test]
+    fn test_drw_planes() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        cpu.i = 0x300;
+        cpu.mem[0x300] = 0x80;
+        cpu.mem[0x301] = 0xC0;
+
+        cpu.execute(Instruction::PLANE(3)).unwrap();
+        cpu.execute(Instruction::DRW(0, 0, 1)).unwrap();
+
+        assert_eq!(cpu.pixels.get(0, 0), 3);
+        assert_eq!(cpu.pixels.get(1, 0), 2);
+        assert_eq!(cpu.v[0xf], 0);
+
+        cpu.execute(Instruction::PLANE(2)).unwrap();
+        cpu.execute(Instruction::CLS).unwrap();
+        assert_eq!(cpu.pixels.get(0, 0), 1);
+        assert_eq!(cpu.pixels.get(1, 0), 0);
+    }
+
+    #[test]
+    fn test_audio_pitch() {
+        let mut cpu = Cpu::init(Quirks::xochip());
+        assert_eq!(cpu.audio_pattern(), None);
+
+        cpu.i = 0x300;
+        cpu.mem[0x300] = 0xAA;
+        cpu.v[2] = 100;
+        cpu.execute(Instruction::AUDIO).unwrap();
+        cpu.execute(Instruction::PITCH(2)).unwrap();
+
+        assert_eq!(cpu.audio_pattern().unwrap()[0], 0xAA);
+        assert_eq!(cpu.pitch(), 100);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.mem[0x200..0x202].clone_from_slice(&[0x22, 0x34]);
+
+        assert_eq!(cpu.peek(), Ok(Instruction::CALL_ADDR(0x234)));
+        assert_eq!(cpu.pc(), START_ADDRESS);
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.pc = 0x202;
+        cpu.execute(Instruction::CALL_ADDR(0x300)).unwrap();
+
+        assert_eq!(cpu.stack(), &[0x202]);
+    }
+
+    #[test]
+    fn test_last_write() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = 0x300;
+        cpu.execute(Instruction::LD_B(0)).unwrap();
+        assert_eq!(cpu.last_write(), Some(0x300..0x303));
+
+        cpu.execute(Instruction::LD_STORE_I(1)).unwrap();
+        assert_eq!(cpu.last_write(), Some(0x300..0x302));
+
+        cpu.execute(Instruction::CLS).unwrap();
+        assert_eq!(cpu.last_write(), None);
+    }
+
+    #[test]
+    fn test_last_read() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.i = 0x300;
+        cpu.execute(Instruction::DRW(0, 0, 4)).unwrap();
+        assert_eq!(cpu.last_read(), Some(0x300..0x304));
+
+        cpu.execute(Instruction::LOAD(3, 1)).unwrap();
+        assert_eq!(cpu.last_read(), Some(0x300..0x303));
+        assert_eq!(cpu.last_write(), None);
+
+        cpu.execute(Instruction::LD_STORE_I(1)).unwrap();
+        assert_eq!(cpu.last_read(), None);
+    }
+
+    #[test]
+    fn test_load_rom_addresses() {
+        let mut rom = Rom::init(vec![0x60, 0x2A]);
+        rom.load_address = 0x600;
+        rom.start_address = 0x600;
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load_rom(&rom).unwrap();
+
+        assert_eq!(cpu.pc(), 0x600);
+        assert_eq!(cpu.rom_hash(), rom.hash());
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.v()[0], 0x2A);
+    }
+
+    #[test]
+    fn test_load_too_large() {
+        let mut cpu = Cpu::init(Quirks::default());
+        let rom = vec![0; MEMORY_SIZE - START_ADDRESS as usize + 1];
+
+        assert_eq!(
+            cpu.load(&rom),
+            Err(RomError::TooLarge {
+                size: rom.len(),
+                max: rom.len() - 1,
+            })
+        );
+        assert_eq!(cpu.rom_hash(), rom::hash(&[]));
+    }
+
+    #[test]
+    fn test_save_state_round_trips() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&[0x60, 0x01, 0x00, 0xFF]).unwrap();
+        cpu.cycle().unwrap();
+        cpu.cycle().unwrap();
+        cpu.pixels.set(3, 4, 2);
+        cpu.keys[7] = true;
+        let state = cpu.save_state();
+
+        let mut restored = Cpu::init(Quirks::default());
+        restored.load(&[0x60, 0x01, 0x00, 0xFF]).unwrap();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.v()[0], 1);
+        assert_eq!(restored.pc(), 0x204);
+        assert_eq!(restored.pixels.get(3, 4), 2);
+        assert!(restored.pixels.hires());
+    }
+
+    #[test]
+    fn test_load_state_errors() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&[0x12, 0x00]).unwrap();
+        let state = cpu.save_state();
+
+        let mut damaged = state.clone();
+        damaged[100] ^= 1;
+        assert_eq!(cpu.load_state(&damaged), Err(StateError::Checksum));
+        assert_eq!(cpu.load_state(b"nope"), Err(StateError::NotAState));
+
+        // a newer version, with a valid checksum
+        let mut future = state[..state.len() - 4].to_vec();
+        future[4..6].copy_from_slice(&3u16.to_be_bytes());
+        let checksum = crc32fast::hash(&future);
+        future.extend_from_slice(&checksum.to_be_bytes());
+        assert_eq!(
+            cpu.load_state(&future),
+            Err(StateError::UnsupportedVersion(3))
+        );
+
+        let mut other = Cpu::init(Quirks::default());
+        other.load(&[0x13, 0x00]).unwrap();
+        other.v[0] = 9;
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::WrongRom { .. })
+        ));
+        assert_eq!(other.v()[0], 9);
+    }
+
+    #[test]
+    fn test_rnd_reseed() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.reseed(1);
+        cpu.execute(Instruction::RND_BYTE(0, 0xFF)).unwrap();
+        cpu.execute(Instruction::RND_BYTE(1, 0xFF)).unwrap();
+        cpu.reseed(1);
+        cpu.execute(Instruction::RND_BYTE(2, 0xFF)).unwrap();
+
+        assert_eq!(cpu.v[0], cpu.v[2]);
+    }
+
+    #[test]
+    fn test_save_state_restores_rng() {
+        let mut cpu = Cpu::init(Quirks::default());
+        let state = cpu.save_state();
+        let draws: Vec<u8> = (0..8).map(|_| cpu.rng.next_u8()).collect();
+        cpu.load_state(&state).unwrap();
+
+        assert_eq!(
+            (0..8).map(|_| cpu.rng.next_u8()).collect::<Vec<u8>>(),
+            draws
+        );
+    }
+
+    #[test]
+    fn test_load_version_1_state() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.v[3] = 3;
+        let state = cpu.save_state();
+
+        // a version 1 state is the same without the trailing rng state
+        let mut old = state[..state.len() - 12].to_vec();
+        old[4..6].copy_from_slice(&1u16.to_be_bytes());
+        let len = u32::from_be_bytes([old[46], old[47], old[48], old[49]]) - 8;
+        old[46..50].copy_from_slice(&len.to_be_bytes());
+        let checksum = crc32fast::hash(&old);
+        old.extend_from_slice(&checksum.to_be_bytes());
+
+        let mut loaded = Cpu::init(Quirks::default());
+        loaded.reseed(5);
+        loaded.load_state(&old).unwrap();
+        assert_eq!(loaded.v()[3], 3);
+        assert_eq!(loaded.rng, Rng::init(5));
+    }
 }
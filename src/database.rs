@@ -0,0 +1,279 @@
+use crate::quirks::Quirks;
+
+use serde_json::Value;
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// The database bundled with the crate, in the format of the community
+/// CHIP-8 database: an array of programs, each with a `title`, `authors` and
+/// its `roms` by hex SHA-1. `data/update.sh` replaces it with the community
+/// database's `programs.json`.
+const BUNDLED: &str = include_str!("../data/roms.json");
+
+/// What the database knows about a ROM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    /// Id of the platform the ROM was written for, e.g. `originalChip8` or
+    /// `xochip`.
+    pub platform: String,
+    /// Quirks of the platform with the ROM's own deviations from it, unless
+    /// this emulator doesn't run the platform.
+    pub quirks: Option<Quirks>,
+    /// Instructions per frame the ROM plays best at.
+    pub tickrate: Option<u32>,
+    /// CHIP-8 key used for each of `up`, `down`, `left`, `right`, `a` and
+    /// `b`.
+    pub keys: BTreeMap<String, u8>,
+}
+
+/// ROM metadata by hex SHA-1.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Database {
+    roms: HashMap<String, RomInfo>,
+}
+
+/// Quirks of a platform by its database id.
+fn platform_quirks(platform: &str) -> Option<Quirks> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(Quirks::vip()),
+        "modernChip8" => Some(Quirks {
+            vf_reset: false,
+            ..Quirks::vip()
+        }),
+        "chip48" => Some(Quirks::chip48()),
+        "superchip1" | "superchip" => Some(Quirks::schip()),
+        "xochip" => Some(Quirks::xochip()),
+        _ => None,
+    }
+}
+
+/// Sets a quirk by its database name. Quirks this emulator doesn't have,
+/// such as `vblank`, are ignored.
+fn set_quirk(quirks: &mut Quirks, name: &str, on: bool) {
+    match name {
+        "logic" => quirks.vf_reset = on,
+        "memoryLeaveIUnchanged" => quirks.increment_i = !on,
+        "shift" => quirks.shift_vx = on,
+        "jump" => quirks.jump_vx = on,
+        "wrap" => quirks.clip_sprites = !on,
+        _ => {}
+    }
+}
+
+impl Database {
+    /// The database bundled with the crate.
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED).expect("bundled rom database is invalid")
+    }
+
+    /// Reads a database such as the community database's `programs.json`.
+    pub fn read(path: &str) -> Result<Database, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| Database::parse(&json))
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(json: &str) -> Result<Database, String> {
+        let programs: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let programs = programs
+            .as_array()
+            .ok_or_else(|| "expected an array of programs".to_string())?;
+        let mut database = Database::default();
+
+        for program in programs {
+            let title = program
+                .get("title")
+                .and_then(Value::as_str)
+                .ok_or_else(|| "program without a title".to_string())?;
+            let authors = match program.get("authors") {
+                Some(authors) => {
+                    strings(authors).ok_or_else(|| format!("{}: authors must be strings", title))?
+                }
+                None => Vec::new(),
+            };
+            let roms = program
+                .get("roms")
+                .and_then(Value::as_object)
+                .ok_or_else(|| format!("{}: roms must be an object", title))?;
+
+            for (hash, rom) in roms {
+                let info = RomInfo::parse(rom, title, &authors)
+                    .map_err(|e| format!("{} ({}): {}", title, hash, e))?;
+                database.roms.insert(hash.to_lowercase(), info);
+            }
+        }
+
+        Ok(database)
+    }
+
+    /// Looks up the ROM with hex SHA-1 `rom_hash`.
+    pub fn lookup(&self, rom_hash: &str) -> Option<&RomInfo> {
+        self.roms.get(&rom_hash.to_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+impl RomInfo {
+    fn parse(rom: &Value, title: &str, authors: &[String]) -> Result<RomInfo, String> {
+        let platforms = rom
+            .get("platforms")
+            .and_then(strings)
+            .filter(|platforms| !platforms.is_empty())
+            .ok_or_else(|| "platforms must be a non-empty array of strings".to_string())?;
+        // the first platform this emulator runs, or else the first listed
+        let platform = platforms
+            .iter()
+            .find(|platform| platform_quirks(platform).is_some())
+            .unwrap_or(&platforms[0])
+            .clone();
+
+        let mut quirks = platform_quirks(&platform);
+        let deviations = rom
+            .get("quirkyPlatforms")
+            .and_then(|quirky| quirky.get(&platform));
+        if let (Some(quirks), Some(deviations)) = (&mut quirks, deviations) {
+            let deviations = deviations
+                .as_object()
+                .ok_or_else(|| "quirkyPlatforms must hold objects".to_string())?;
+            for (name, on) in deviations {
+                let on = on
+                    .as_bool()
+                    .ok_or_else(|| format!("quirk {} must be true or false", name))?;
+                set_quirk(quirks, name, on);
+            }
+        }
+
+        let tickrate = match rom.get("tickrate") {
+            Some(tickrate) => Some(
+                tickrate
+                    .as_u64()
+                    .and_then(|tickrate| u32::try_from(tickrate).ok())
+                    .filter(|&tickrate| tickrate > 0)
+                    .ok_or_else(|| "tickrate must be a positive number".to_string())?,
+            ),
+            None => None,
+        };
+
+        let mut keys = BTreeMap::new();
+        if let Some(hints) = rom.get("keys") {
+            let hints = hints
+                .as_object()
+                .ok_or_else(|| "keys must be an object".to_string())?;
+            for (name, key) in hints {
+                let key = key
+                    .as_u64()
+                    .filter(|&key| key < 16)
+                    .ok_or_else(|| format!("key {} must be a CHIP-8 key", name))?;
+                keys.insert(name.clone(), key as u8);
+            }
+        }
+
+        Ok(RomInfo {
+            title: title.to_string(),
+            authors: authors.to_vec(),
+            platform,
+            quirks,
+            tickrate,
+            keys,
+        })
+    }
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn program(rom: &str) -> String {
+        format!(
+            r#"[{{"title": "Game", "authors": ["Someone"], "roms": {{"{}": {}}}}}]"#,
+            HASH, rom
+        )
+    }
+
+    #[test]
+    fn test_bundled() {
+        let database = Database::bundled();
+        assert!(!database.is_empty());
+
+        let rom = std::fs::read("roms/test_opcode.ch8").unwrap();
+        let info = database.lookup(&crate::rom::hash(&rom)).unwrap();
+        assert_eq!(info.platform, "originalChip8");
+        assert_eq!(info.quirks, Some(Quirks::vip()));
+    }
+
+    #[test]
+    fn test_read() {
+        assert_eq!(Database::read("data/roms.json"), Ok(Database::bundled()));
+        assert!(Database::read("data/missing.json")
+            .unwrap_err()
+            .starts_with("data/missing.json: "));
+    }
+
+    #[test]
+    fn test_parse() {
+        let database = Database::parse(&program(
+            r#"{"platforms": ["superchip", "xochip"], "tickrate": 30,
+                "quirkyPlatforms": {"superchip": {"shift": false, "vblank": true}},
+                "keys": {"up": 5, "a": 6}}"#,
+        ))
+        .unwrap();
+
+        let info = database.lookup(&HASH.to_uppercase()).unwrap();
+        assert_eq!(info.title, "Game");
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.platform, "superchip");
+        assert_eq!(
+            info.quirks,
+            Some(Quirks {
+                shift_vx: false,
+                ..Quirks::schip()
+            })
+        );
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.keys.get("up"), Some(&5));
+        assert_eq!(database.lookup("another rom"), None);
+    }
+
+    #[test]
+    fn test_unknown_platform() {
+        let database =
+            Database::parse(&program(r#"{"platforms": ["megachip8", "chip48"]}"#)).unwrap();
+        assert_eq!(database.lookup(HASH).unwrap().platform, "chip48");
+
+        let database = Database::parse(&program(r#"{"platforms": ["megachip8"]}"#)).unwrap();
+        let info = database.lookup(HASH).unwrap();
+        assert_eq!(info.platform, "megachip8");
+        assert_eq!(info.quirks, None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Database::parse("{}").is_err());
+        assert!(Database::parse(&program(r#"{"platforms": []}"#)).is_err());
+        assert!(
+            Database::parse(&program(r#"{"platforms": ["xochip"], "keys": {"up": 16}}"#)).is_err()
+        );
+        assert!(Database::parse(&program(r#"{"platforms": ["xochip"], "tickrate": 0}"#)).is_err());
+    }
+}
@@ -0,0 +1,684 @@
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::rewind::Rewind;
+
+use std::fmt;
+
+const HELP: &str = "commands:
+    c, continue              run until a breakpoint or watchpoint is hit
+    p, pause                 stop running
+    s, step [n]              execute n instructions (default 1)
+    n, next                  step, running over subroutine calls
+    f, finish                run until the current subroutine returns
+    rs, reverse-step         undo the last instruction
+    b, break <addr> [if <register> <op> <value>]
+                             stop before executing addr, optionally only
+                             while the condition holds, e.g. b 0x2a4 if v3 == 5
+    w, watch <addr>          stop after an instruction writes to addr
+    d, delete <addr>         remove breakpoints and watchpoints at addr
+    l, list                  show breakpoints and watchpoints
+    r, registers             show registers
+    q, quit                  exit the emulator
+
+registers are v0 to vf, i, dt, st and sp; ops are == != < <= > >=
+numbers are decimal, or hexadecimal with a 0x prefix";
+
+/// A register a breakpoint condition can test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(u8),
+    I,
+    Dt,
+    St,
+    Sp,
+}
+
+impl Register {
+    fn parse(name: &str) -> Result<Register, String> {
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "i" => Ok(Register::I),
+            "dt" => Ok(Register::Dt),
+            "st" => Ok(Register::St),
+            "sp" => Ok(Register::Sp),
+            _ => name
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register {}", name)),
+        }
+    }
+
+    fn read(self, cpu: &Cpu) -> u16 {
+        match self {
+            Register::V(x) => cpu.v()[x as usize] as u16,
+            Register::I => cpu.i(),
+            Register::Dt => cpu.dt() as u16,
+            Register::St => cpu.st() as u16,
+            Register::Sp => cpu.sp() as u16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A test of a register against a value, such as `v3 == 5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (register, comparison, value) = match words.as_slice() {
+            [register, comparison, value] => (register, comparison, value),
+            _ => return Err(format!("expected <register> <op> <value>, got {}", text)),
+        };
+
+        let comparison = match *comparison {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            op => return Err(format!("unknown comparison {}", op)),
+        };
+
+        Ok(Condition {
+            register: Register::parse(register)?,
+            comparison,
+            value: parse_number(value)?,
+        })
+    }
+
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        let register = self.register.read(cpu);
+        match self.comparison {
+            Comparison::Eq => register == self.value,
+            Comparison::Ne => register != self.value,
+            Comparison::Lt => register < self.value,
+            Comparison::Le => register <= self.value,
+            Comparison::Gt => register > self.value,
+            Comparison::Ge => register >= self.value,
+        }
+    }
+}
+
+/// Stops execution before the instruction at `addr` runs, if `condition`
+/// holds at that point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+/// A debugger command, as typed at the prompt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Continue,
+    Pause,
+    Step(u32),
+    Next,
+    Finish,
+    ReverseStep,
+    Break(Breakpoint),
+    Watch(u16),
+    Delete(u16),
+    List,
+    Registers,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, args) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], line[split..].trim()),
+            None => (line, ""),
+        };
+
+        let command = match name {
+            "c" | "continue" => Command::Continue,
+            "p" | "pause" => Command::Pause,
+            "s" | "step" if args.is_empty() => Command::Step(1),
+            "s" | "step" => Command::Step(parse_number(args)? as u32),
+            "n" | "next" => Command::Next,
+            "f" | "finish" => Command::Finish,
+            "rs" | "reverse-step" => Command::ReverseStep,
+            "b" | "break" => {
+                let (addr, condition) = match args.find(" if ") {
+                    Some(split) => (&args[..split], Some(Condition::parse(&args[split + 4..])?)),
+                    None => (args, None),
+                };
+                Command::Break(Breakpoint {
+                    addr: parse_number(addr.trim())?,
+                    condition,
+                })
+            }
+            "w" | "watch" => Command::Watch(parse_number(args)?),
+            "d" | "delete" => Command::Delete(parse_number(args)?),
+            "l" | "list" => Command::List,
+            "r" | "registers" => Command::Registers,
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(format!("unknown command {}, try help", name)),
+        };
+
+        Ok(command)
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    parsed.map_err(|_| format!("invalid number {}", text))
+}
+
+/// Why the debugger paused execution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Paused,
+    Step,
+    Return,
+    Breakpoint(u16),
+    Watchpoint(u16),
+    Exited,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Paused => write!(f, "paused"),
+            Stop::Step => write!(f, "stepped"),
+            Stop::Return => write!(f, "returned"),
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at {:#05x}", addr),
+            Stop::Watchpoint(addr) => write!(f, "watchpoint at {:#05x} written", addr),
+            Stop::Exited => write!(f, "program exited"),
+            Stop::Error(error) => write!(f, "error: {}", error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Paused,
+    Running,
+    // instructions left to step
+    Step(u32),
+    // stack depth to step over a call from
+    StepOver(u8),
+    // stack depth of the subroutine to finish
+    Finish(u8),
+}
+
+/// Runs a [`Cpu`] under breakpoints, watchpoints and single stepping.
+///
+/// Frontends feed it [`Command`]s and call [`Debugger::run_frame`] in place
+/// of [`Cpu::run_frame`]. Timers still tick once per `ipf` instructions, so
+/// stepping through a program sees the same timer values as running it.
+///
+/// While stepping, a snapshot is taken before every instruction so that
+/// [`Command::ReverseStep`] can undo it. [`Command::Continue`] runs without
+/// them at full speed, so reverse stepping can't go back past it.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<u16>,
+    mode: Mode,
+    // ignore the breakpoint at pc on resuming, so execution can leave it
+    resuming: bool,
+    // instructions executed since the last timer tick
+    cycles: u32,
+    // instructions per frame of the latest run_frame
+    ipf: u32,
+    // snapshots before each stepped instruction, for reverse stepping
+    history: Rewind,
+}
+
+/// Instructions kept for reverse stepping.
+const HISTORY_LENGTH: usize = 10_000;
+/// Memory the reverse stepping history may use, in bytes.
+const HISTORY_BUDGET: usize = 32 << 20;
+
+impl Debugger {
+    /// Creates a debugger with the program paused before its first
+    /// instruction.
+    pub fn init() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            mode: Mode::Paused,
+            resuming: false,
+            cycles: 0,
+            ipf: 1,
+            history: Rewind::init(HISTORY_LENGTH, HISTORY_BUDGET),
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    fn resume(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.resuming = true;
+    }
+
+    /// Carries out `command`, returning the text to show for it.
+    ///
+    /// [`Command::Quit`] is left to the frontend and does nothing here.
+    pub fn execute(&mut self, cpu: &mut Cpu, command: Command) -> Result<String, String> {
+        let resumes = matches!(
+            command,
+            Command::Continue
+                | Command::Step(_)
+                | Command::Next
+                | Command::Finish
+                | Command::ReverseStep
+        );
+        if resumes && !self.paused() {
+            return Err("already running, pause first".to_string());
+        }
+
+        let output = match command {
+            Command::Continue => {
+                // nothing is recorded while running, so older steps can't be
+                // returned to in order
+                self.history.clear();
+                self.resume(Mode::Running);
+                String::new()
+            }
+            Command::Pause => {
+                self.mode = Mode::Paused;
+                Debugger::status(cpu)
+            }
+            Command::Step(n) => {
+                self.resume(Mode::Step(n.max(1)));
+                String::new()
+            }
+            Command::Next => {
+                match cpu.peek() {
+                    Ok(Instruction::CALL_ADDR(_)) => self.resume(Mode::StepOver(cpu.sp())),
+                    _ => self.resume(Mode::Step(1)),
+                }
+                String::new()
+            }
+            Command::Finish => {
+                if cpu.sp() == 0 {
+                    return Err("not inside a subroutine".to_string());
+                }
+                self.resume(Mode::Finish(cpu.sp()));
+                String::new()
+            }
+            Command::ReverseStep => {
+                if !self.history.undo(cpu).map_err(|e| e.to_string())? {
+                    return Err("no earlier instruction to return to".to_string());
+                }
+                // every instruction counted one cycle towards the timer tick
+                self.cycles = (self.cycles + self.ipf - 1) % self.ipf;
+                Debugger::status(cpu)
+            }
+            Command::Break(breakpoint) => {
+                self.breakpoints.push(breakpoint);
+                format!("breakpoint at {:#05x}", breakpoint.addr)
+            }
+            Command::Watch(addr) => {
+                self.watchpoints.push(addr);
+                format!("watchpoint at {:#05x}", addr)
+            }
+            Command::Delete(addr) => {
+                self.breakpoints
+                    .retain(|breakpoint| breakpoint.addr != addr);
+                self.watchpoints.retain(|&watchpoint| watchpoint != addr);
+                format!("deleted {:#05x}", addr)
+            }
+            Command::List => self.list(),
+            Command::Registers => Debugger::status(cpu),
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        };
+
+        Ok(output)
+    }
+
+    fn list(&self) -> String {
+        let mut lines = Vec::new();
+        for breakpoint in &self.breakpoints {
+            let mut line = format!("break {:#05x}", breakpoint.addr);
+            if let Some(condition) = breakpoint.condition {
+                line += &format!(
+                    " if {:?} {:?} {}",
+                    condition.register, condition.comparison, condition.value
+                );
+            }
+            lines.push(line);
+        }
+        for watchpoint in &self.watchpoints {
+            lines.push(format!("watch {:#05x}", watchpoint));
+        }
+
+        lines.join("\n")
+    }
+
+    fn breakpoint_hit(&self, cpu: &Cpu) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.addr == cpu.pc()
+                && breakpoint
+                    .condition
+                    .is_none_or(|condition| condition.holds(cpu))
+        })
+    }
+
+    /// Runs up to `ipf` instructions unless paused, returning why execution
+    /// stopped if it did.
+    pub fn run_frame(&mut self, cpu: &mut Cpu, ipf: u32) -> Option<Stop> {
+        self.ipf = ipf.max(1);
+        for _ in 0..ipf {
+            if self.paused() {
+                return None;
+            }
+            let stop = self.cycle(cpu, ipf);
+            if stop.is_some() {
+                self.mode = Mode::Paused;
+                return stop;
+            }
+        }
+
+        None
+    }
+
+    fn cycle(&mut self, cpu: &mut Cpu, ipf: u32) -> Option<Stop> {
+        if cpu.exited() {
+            return Some(Stop::Exited);
+        }
+        if !self.resuming && self.breakpoint_hit(cpu) {
+            return Some(Stop::Breakpoint(cpu.pc()));
+        }
+        self.resuming = false;
+
+        let recording = self.mode != Mode::Running;
+        if recording {
+            self.history.record(cpu);
+        }
+        if let Err(error) = cpu.cycle() {
+            // nothing ran, so drop the snapshot again
+            if recording {
+                self.history.discard();
+            }
+            return Some(Stop::Error(error));
+        }
+        self.cycles += 1;
+        if self.cycles >= ipf {
+            cpu.tick_timers();
+            self.cycles = 0;
+        }
+
+        if let Some(written) = cpu.last_write() {
+            let watched = self
+                .watchpoints
+                .iter()
+                .find(|&&addr| written.contains(&(addr as usize)));
+            if let Some(&addr) = watched {
+                return Some(Stop::Watchpoint(addr));
+            }
+        }
+
+        match self.mode {
+            Mode::Step(1) => Some(Stop::Step),
+            Mode::Step(n) => {
+                self.mode = Mode::Step(n - 1);
+                None
+            }
+            Mode::StepOver(depth) if cpu.sp() <= depth => Some(Stop::Step),
+            Mode::Finish(depth) if cpu.sp() < depth => Some(Stop::Return),
+            _ => None,
+        }
+    }
+
+    /// Formats the registers, the stack and the next instruction for a
+    /// terminal.
+    pub fn status(cpu: &Cpu) -> String {
+        let mut lines = vec![format!(
+            "PC {:#06x}  I {:#06x}  SP {}  DT {:02x}  ST {:02x}",
+            cpu.pc(),
+            cpu.i(),
+            cpu.sp(),
+            cpu.dt(),
+            cpu.st()
+        )];
+        for row in cpu.v().chunks(8).enumerate() {
+            let (half, registers) = row;
+            let registers: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(x, value)| format!("V{:X} {:02x}", half * 8 + x, value))
+                .collect();
+            lines.push(registers.join("  "));
+        }
+
+        let stack: Vec<String> = cpu
+            .stack()
+            .iter()
+            .map(|addr| format!("{:#06x}", addr))
+            .collect();
+        lines.push(format!("stack [{}]", stack.join(", ")));
+
+        match cpu.peek() {
+            Ok(inst) => lines.push(format!("=> {:#06x}  {}", cpu.pc(), inst)),
+            Err(error) => lines.push(format!("=> {:#06x}  {}", cpu.pc(), error)),
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // 0x200 CALL 0x206
+    // 0x202 LD V0, 1
+    // 0x204 JP 0x204
+    // 0x206 ADD V1, 1
+    // 0x208 LD B, V1
+    // 0x20A RET
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&[
+            0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x71, 0x01, 0xF1, 0x33, 0x00, 0xEE,
+        ])
+        .unwrap();
+        cpu.execute(Instruction::LD_I(0x300)).unwrap();
+
+        cpu
+    }
+
+    fn run(debugger: &mut Debugger, cpu: &mut Cpu, line: &str) -> Option<Stop> {
+        let command = Command::parse(line).unwrap();
+        debugger.execute(cpu, command).unwrap();
+
+        // plenty of frames for the little test program
+        (0..10).find_map(|_| debugger.run_frame(cpu, 10))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 0x10"), Ok(Command::Step(16)));
+        assert_eq!(Command::parse("w 0x300"), Ok(Command::Watch(0x300)));
+        assert_eq!(
+            Command::parse("b 0x206 if v1 >= 2"),
+            Ok(Command::Break(Breakpoint {
+                addr: 0x206,
+                condition: Some(Condition {
+                    register: Register::V(1),
+                    comparison: Comparison::Ge,
+                    value: 2,
+                }),
+            }))
+        );
+        assert!(Command::parse("b 0x206 if vg == 2").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn test_starts_paused() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(debugger.run_frame(&mut cpu, 10), None);
+        assert_eq!(cpu.pc(), 0x200);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(run(&mut debugger, &mut cpu, "step"), Some(Stop::Step));
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(run(&mut debugger, &mut cpu, "step 2"), Some(Stop::Step));
+        assert_eq!(cpu.pc(), 0x20A);
+    }
+
+    #[test]
+    fn test_next_steps_over_call() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(run(&mut debugger, &mut cpu, "next"), Some(Stop::Step));
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(cpu.v()[1], 1);
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "step");
+
+        assert_eq!(run(&mut debugger, &mut cpu, "finish"), Some(Stop::Return));
+        assert_eq!(cpu.pc(), 0x202);
+        assert!(debugger.execute(&mut cpu, Command::Finish).is_err());
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "break 0x20A");
+
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "continue"),
+            Some(Stop::Breakpoint(0x20A))
+        );
+        assert_eq!(cpu.pc(), 0x20A);
+
+        // continuing leaves the breakpoint instead of stopping at it again
+        assert_eq!(run(&mut debugger, &mut cpu, "continue"), None);
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "break 0x208 if v1 == 2");
+
+        assert_eq!(run(&mut debugger, &mut cpu, "continue"), None);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "watch 0x302");
+
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "continue"),
+            Some(Stop::Watchpoint(0x302))
+        );
+        assert_eq!(cpu.pc(), 0x20A);
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        assert!(debugger.execute(&mut cpu, Command::ReverseStep).is_err());
+
+        run(&mut debugger, &mut cpu, "step 2");
+        assert_eq!(cpu.mem()[0x302], 0);
+        run(&mut debugger, &mut cpu, "step");
+        assert_eq!(cpu.mem()[0x302], 1);
+
+        run(&mut debugger, &mut cpu, "reverse-step");
+        assert_eq!(cpu.pc(), 0x208);
+        assert_eq!(cpu.mem()[0x302], 0);
+        run(&mut debugger, &mut cpu, "rs");
+        run(&mut debugger, &mut cpu, "rs");
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.v()[1], 0);
+    }
+
+    #[test]
+    fn test_continue_forgets_history() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "step 2");
+        run(&mut debugger, &mut cpu, "break 0x204");
+
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "continue"),
+            Some(Stop::Breakpoint(0x204))
+        );
+        assert!(debugger.history.is_empty());
+        assert!(debugger.execute(&mut cpu, Command::ReverseStep).is_err());
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::init();
+        run(&mut debugger, &mut cpu, "continue");
+        assert!(!debugger.paused());
+        assert!(debugger.execute(&mut cpu, Command::Step(1)).is_err());
+
+        debugger.execute(&mut cpu, Command::Pause).unwrap();
+        assert!(debugger.paused());
+    }
+
+    #[test]
+    fn test_status() {
+        let cpu = cpu();
+        let status = Debugger::status(&cpu);
+
+        assert!(status.starts_with("PC 0x0200  I 0x0300  SP 0"));
+        assert!(status.contains("stack []"));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::cpu::START_ADDRESS;
+use crate::instruction::Instruction;
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// column the address and bytes comment starts at
+const COMMENT_COLUMN: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Label {
+    Jump,
+    Call,
+}
+
+/// Instructions reachable from [`START_ADDRESS`], and the jump and call
+/// targets among them.
+struct Trace {
+    code: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, Label>,
+}
+
+/// Decodes the instruction at `addr` of a ROM loaded at [`START_ADDRESS`].
+fn decode(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let word = |addr: u16| {
+        let offset = addr.checked_sub(START_ADDRESS)? as usize;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+
+    match Instruction::parse(word(addr)?).ok()? {
+        Instruction::LD_I_LONG(_) => Some(Instruction::LD_I_LONG(word(addr.checked_add(2)?)?)),
+        inst => Some(inst),
+    }
+}
+
+/// Follows every path of execution from [`START_ADDRESS`], so that sprites
+/// and other data between routines are never mistaken for code.
+///
+/// `JP V0, addr` jumps to a computed address and ends the path.
+fn trace(rom: &[u8]) -> Trace {
+    let mut code = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![START_ADDRESS];
+
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let inst = match decode(rom, addr) {
+            Some(inst) => inst,
+            None => continue,
+        };
+
+        let next = addr.wrapping_add(inst.size());
+        match inst {
+            Instruction::JP_ADDR(target) => {
+                labels.entry(target).or_insert(Label::Jump);
+                pending.push(target);
+            }
+            Instruction::CALL_ADDR(target) => {
+                labels.insert(target, Label::Call);
+                pending.push(target);
+                pending.push(next);
+            }
+            Instruction::RET | Instruction::EXIT | Instruction::JP_V0(_) => {}
+            Instruction::SE_BYTE(..)
+            | Instruction::SNE_BYTE(..)
+            | Instruction::SE(..)
+            | Instruction::SNE(..)
+            | Instruction::SKP(_)
+            | Instruction::SKNP(_) => {
+                let skipped = decode(rom, next).map_or(2, |inst| inst.size());
+                pending.push(next);
+                pending.push(next.wrapping_add(skipped));
+            }
+            _ => pending.push(next),
+        }
+        code.insert(addr, inst);
+    }
+
+    Trace { code, labels }
+}
+
+fn label_name(addr: u16, label: Label) -> String {
+    match label {
+        Label::Jump => format!("label_{:03x}", addr),
+        Label::Call => format!("sub_{:03x}", addr),
+    }
+}
+
+/// Disassembles a ROM into assembly source, one instruction or data
+/// directive per line with its address and raw bytes in a trailing comment.
+///
+/// Jump and call targets get labels, and words that no path of execution
+/// reaches are written as `db` data.
+pub fn disassemble(rom: &[u8]) -> String {
+    let Trace { code, labels } = trace(rom);
+    let end = START_ADDRESS as usize + rom.len();
+    let boundary = |range: Range<usize>| {
+        let range = range.start as u16..range.end.min(u16::MAX as usize) as u16;
+        code.range(range.clone()).next().is_some() || labels.range(range).next().is_some()
+    };
+
+    // split the rom into lines, decoding instructions that don't overlap a
+    // label or another instruction
+    let mut lines = Vec::new();
+    let mut addr = START_ADDRESS as usize;
+    while addr < end {
+        let inst = code
+            .get(&(addr as u16))
+            .filter(|inst| !boundary(addr + 1..addr + inst.size() as usize));
+        let len = match inst {
+            Some(inst) => inst.size() as usize,
+            None if addr + 1 < end && !boundary(addr + 1..addr + 2) => 2,
+            None => 1,
+        };
+        lines.push((addr, len, inst));
+        addr += len;
+    }
+
+    let target = |addr: u16| match labels.get(&addr) {
+        Some(&label) if lines.iter().any(|&(start, _, _)| start == addr as usize) => {
+            label_name(addr, label)
+        }
+        _ => format!("{:#05x}", addr),
+    };
+
+    let mut out = String::new();
+    for &(addr, len, inst) in &lines {
+        if let Some(&label) = labels.get(&(addr as u16)) {
+            out += &format!("{}:\n", label_name(addr as u16, label));
+        }
+
+        let bytes = &rom[addr - START_ADDRESS as usize..][..len];
+        let text = match inst {
+            Some(Instruction::JP_ADDR(to)) => format!("JP {}", target(*to)),
+            Some(Instruction::CALL_ADDR(to)) => format!("CALL {}", target(*to)),
+            Some(inst) => inst.to_string(),
+            None => {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+                format!("db {}", bytes.join(", "))
+            }
+        };
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        out += &format!(
+            "    {:<width$}; {:03x}: {}\n",
+            text,
+            addr,
+            bytes.join(" "),
+            width = COMMENT_COLUMN - 4
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let rom = [
+            0x22, 0x08, // CALL sub_208
+            0xA2, 0x0C, // LD I, 0x20c
+            0xD0, 0x11, // DRW V0, V1, 1
+            0x12, 0x06, // JP label_206
+            0x60, 0x23, // LD V0, 0x23
+            0x00, 0xEE, // RET
+            0xFF, 0x81, // sprite data
+        ];
+
+        let expected = "    CALL sub_208            ; 200: 22 08
+    LD I, 0x20c             ; 202: a2 0c
+    DRW V0, V1, 1           ; 204: d0 11
+label_206:
+    JP label_206            ; 206: 12 06
+sub_208:
+    LD V0, 0x23             ; 208: 60 23
+    RET                     ; 20a: 00 ee
+    db 0xff, 0x81           ; 20c: ff 81
+";
+        assert_eq!(disassemble(&rom), expected);
+    }
+
+    #[test]
+    fn test_skips_follow_both_paths() {
+        let rom = [
+            0x30, 0x00, // SE V0, 0x00
+            0x00, 0xFD, // EXIT
+            0x00, 0xFD, // EXIT
+            0x00, 0xFD, // never reached
+        ];
+
+        let out = disassemble(&rom);
+        assert_eq!(out.matches("EXIT").count(), 2);
+        assert!(out.contains("db 0x00, 0xfd"));
+    }
+
+    #[test]
+    fn test_long_load_is_one_line() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+
+        let out = disassemble(&rom);
+        assert!(out.starts_with("    LD I, LONG 0x1234       ; 200: f0 00 12 34\n"));
+    }
+
+    #[test]
+    fn test_invalid_words_are_data() {
+        // 9001 doesn't decode, so execution never gets past it
+        let rom = [0x90, 0x01, 0x00, 0xFD, 0x00, 0xFD];
+
+        assert!(disassemble(&rom).starts_with("    db 0x90, 0x01           ; 200: 90 01\n"));
+    }
+
+    #[test]
+    fn test_odd_trailing_byte() {
+        let rom = [0x00, 0xFD, 0xAA];
+
+        assert!(disassemble(&rom).ends_with("    db 0xaa                 ; 202: aa\n"));
+    }
+}
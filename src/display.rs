@@ -1,30 +1,54 @@
-use std::convert::TryInto;
-
-use sdl2;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::video::{FullscreenType, Window};
+
+use crate::config::DisplayConfig;
+use crate::palette::Rgb;
+
+use chip8::framebuffer::HEIGHT;
+use chip8::framebuffer::WIDTH;
+use chip8::Framebuffer;
 
-use crate::cpu::WIDTH;
-use crate::cpu::HEIGHT;
+/// Smallest pixel, in window pixels, that still gets grid lines.
+const MIN_GRID_CELL: f32 = 4.0;
 
-const SCALE: usize = 10;
+/// A symbol in the top right corner showing the emulator's own state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indicator {
+    None,
+    /// Two bars.
+    Paused,
+    /// Two triangles pointing right.
+    Fast,
+    /// One triangle pointing right.
+    Slow,
+}
+
+fn color((r, g, b): Rgb) -> Color {
+    Color::RGB(r, g, b)
+}
 
 pub struct Display {
     canvas: Canvas<Window>,
+    // one color per XO-CHIP plane combination; plain CHIP-8 only uses the first two
+    colors: [Color; 4],
+    grid: Option<Color>,
+    // scale by any factor rather than whole window pixels per pixel
+    fit: bool,
 }
 
 impl Display {
-    pub fn init(sdl_context: &sdl2::Sdl) -> Display {
+    pub fn init(sdl_context: &sdl2::Sdl, config: &DisplayConfig) -> Display {
         let video_subsystem = sdl_context.video().unwrap();
 
-        let window_width = ((WIDTH * SCALE) as u32).try_into().unwrap();
-        let window_height = ((HEIGHT * SCALE) as u32).try_into().unwrap();
+        let window_width = WIDTH as u32 * config.scale;
+        let window_height = HEIGHT as u32 * config.scale;
 
         let window = video_subsystem
             .window("chip8", window_width, window_height)
             .position_centered()
+            .resizable()
             .build()
             .expect("could not initialize video subsystem");
 
@@ -33,31 +57,129 @@ impl Display {
             .build()
             .expect("could not make a canvas");
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        let palette = config.palette;
+        canvas.set_draw_color(color(palette.colors[0]));
         canvas.clear();
         canvas.present();
 
-        Display { canvas }
+        Display {
+            canvas,
+            colors: [
+                color(palette.colors[0]),
+                color(palette.colors[1]),
+                color(palette.colors[2]),
+                color(palette.colors[3]),
+            ],
+            grid: if config.grid {
+                Some(color(palette.grid()))
+            } else {
+                None
+            },
+            fit: config.fit,
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).ok();
     }
 
-    fn draw_pixel(&mut self, x: usize, y: usize, pixel: &bool) {
-        let color = match pixel {
-            true => Color::RGB(255, 255, 255),
-            false => Color::RGB(0, 0, 0),
+    /// Switches between the window and fullscreen at the desktop resolution.
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
         };
+        if let Err(e) = window.set_fullscreen(fullscreen) {
+            eprintln!("could not toggle fullscreen: {}", e);
+        }
+    }
 
-        self.canvas.set_draw_color(color);
-        let _ = self
-            .canvas
-            .fill_rect(Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE as u32, SCALE as u32));
+    /// Draws `indicator` in a `size` pixel square with its top left corner at
+    /// `x`, `y`.
+    fn draw_indicator(&mut self, indicator: Indicator, x: i32, y: i32, size: u32) {
+        let bar = (size / 3).max(1);
+        // a right pointing triangle as rows that widen then narrow again
+        let triangle = |left: i32, width: u32| {
+            (0..size).map(move |row| {
+                let reach = row.min(size - 1 - row) * 2 * width / size;
+                Rect::new(left, y + row as i32, reach.max(1), 1)
+            })
+        };
+        let rects: Vec<Rect> = match indicator {
+            Indicator::None => return,
+            Indicator::Paused => vec![
+                Rect::new(x, y, bar, size),
+                Rect::new(x + (size - bar) as i32, y, bar, size),
+            ],
+            Indicator::Fast => triangle(x, size / 2)
+                .chain(triangle(x + (size / 2) as i32, size / 2))
+                .collect(),
+            Indicator::Slow => triangle(x + (size / 4) as i32, size / 2).collect(),
+        };
+
+        self.canvas.set_draw_color(self.colors[1]);
+        let _ = self.canvas.fill_rects(&rects);
     }
 
-    pub fn render(&mut self, pixels: &[[bool; WIDTH]; HEIGHT]) {
-        for (y, row) in pixels.iter().enumerate() {
+    /// Draws the framebuffer as large as the window allows while keeping its
+    /// aspect ratio, centered between bars of the background color. Pixels
+    /// are a whole number of window pixels unless fitting, and hi-res pixels
+    /// come out half the size.
+    pub fn render(&mut self, pixels: &Framebuffer, indicator: Indicator) {
+        let (window_width, window_height) = self
+            .canvas
+            .output_size()
+            .unwrap_or((WIDTH as u32, HEIGHT as u32));
+        let (width, height) = (pixels.width() as f32, pixels.height() as f32);
+        let mut cell = (window_width as f32 / width).min(window_height as f32 / height);
+        if !self.fit {
+            cell = cell.floor().max(1.0);
+        }
+        let left = (window_width as f32 - width * cell) / 2.0;
+        let top = (window_height as f32 - height * cell) / 2.0;
+        // edges of the nth pixel, so neighbours share them without gaps
+        let x_edge = |x: usize| (left + x as f32 * cell).round() as i32;
+        let y_edge = |y: usize| (top + y as f32 * cell).round() as i32;
+
+        self.canvas.set_draw_color(self.colors[0]);
+        self.canvas.clear();
+
+        for (y, row) in pixels.rows().enumerate() {
             for (x, pixel) in row.iter().enumerate() {
-                self.draw_pixel(x, y, pixel);
+                self.canvas
+                    .set_draw_color(self.colors[*pixel as usize & 0b11]);
+                let _ = self.canvas.fill_rect(Rect::new(
+                    x_edge(x),
+                    y_edge(y),
+                    (x_edge(x + 1) - x_edge(x)) as u32,
+                    (y_edge(y + 1) - y_edge(y)) as u32,
+                ));
+            }
+        }
+
+        if let Some(grid) = self.grid.filter(|_| cell >= MIN_GRID_CELL) {
+            let (columns, rows) = (pixels.width(), pixels.height());
+            let screen_width = (x_edge(columns) - x_edge(0)) as u32;
+            let screen_height = (y_edge(rows) - y_edge(0)) as u32;
+            self.canvas.set_draw_color(grid);
+            for x in 0..=columns {
+                let _ = self
+                    .canvas
+                    .fill_rect(Rect::new(x_edge(x), y_edge(0), 1, screen_height));
+            }
+            for y in 0..=rows {
+                let _ = self
+                    .canvas
+                    .fill_rect(Rect::new(x_edge(0), y_edge(y), screen_width, 1));
             }
         }
+
+        // a little larger than a font character
+        let size = (cell * pixels.width() as f32 / 12.0).max(6.0) as u32;
+        let margin = (size / 3) as i32;
+        let corner = x_edge(pixels.width()) - size as i32 - margin;
+        self.draw_indicator(indicator, corner, y_edge(0) + margin, size);
         self.canvas.present();
     }
 }
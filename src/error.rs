@@ -0,0 +1,42 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while decoding or executing a program.
+///
+/// Every variant records the address of the faulting instruction (`pc`) and
+/// its raw instruction word (`opcode`) so a frontend can point at the
+/// offending spot in the ROM.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Chip8Error {
+    /// The word does not decode to any known instruction.
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// `CALL` with all 16 stack entries in use.
+    StackOverflow { pc: u16, opcode: u16 },
+    /// `RET` with an empty stack.
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// An access to `addr`, which lies outside of memory.
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04x} at {:03x}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow by {:04x} at {:03x}", opcode, pc)
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow by {:04x} at {:03x}", opcode, pc)
+            }
+            Chip8Error::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "memory access to {:x} out of bounds by {:04x} at {:03x}",
+                addr, opcode, pc
+            ),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
@@ -0,0 +1,213 @@
+/// Width of the display in the default low resolution mode.
+pub const WIDTH: usize = 64;
+/// Height of the display in the default low resolution mode.
+pub const HEIGHT: usize = 32;
+
+/// Width of the display in SUPER-CHIP high resolution mode.
+pub const HIRES_WIDTH: usize = 128;
+/// Height of the display in SUPER-CHIP high resolution mode.
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Number of XO-CHIP bitplanes.
+pub const PLANES: usize = 2;
+
+/// A display of up to [`PLANES`] bitplanes whose resolution can change at
+/// runtime.
+///
+/// Each pixel holds one bit per plane, so it reads back as a color index
+/// from 0 to 3. Plain CHIP-8 and SUPER-CHIP programs only ever draw to plane
+/// 1 and see a monochrome display of 0s and 1s. Operations that take a
+/// `planes` mask only touch the planes whose bits are set in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    // row-major, `width * height` entries
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// Creates a blank `width` by `height` display.
+    pub fn init(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the display is in 128x64 high resolution mode.
+    pub fn hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    /// Changes the resolution, clearing every plane.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Framebuffer::init(width, height);
+    }
+
+    /// Returns the color index of a pixel, one bit per plane.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Flips the pixel on `planes`, returning whether any of those planes
+    /// was lit before.
+    pub fn toggle(&mut self, x: usize, y: usize, planes: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collision = *pixel & planes != 0;
+        *pixel ^= planes;
+
+        collision
+    }
+
+    /// Turns every pixel off on `planes`.
+    pub fn clear(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    /// Iterates over the rows of the display, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// Moves the picture on `planes` down by `n` pixels. Rows scrolled in are
+    /// blank.
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height) * self.width;
+
+        for i in (0..self.pixels.len()).rev() {
+            let above = match i >= n {
+                true => self.pixels[i - n] & planes,
+                false => 0,
+            };
+            self.pixels[i] = (self.pixels[i] & !planes) | above;
+        }
+    }
+
+    /// Moves the picture on `planes` right by `n` pixels. Columns scrolled in
+    /// are blank.
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+
+        for row in self.pixels.chunks_mut(self.width) {
+            for x in (0..row.len()).rev() {
+                let left = match x >= n {
+                    true => row[x - n] & planes,
+                    false => 0,
+                };
+                row[x] = (row[x] & !planes) | left;
+            }
+        }
+    }
+
+    /// Moves the picture on `planes` left by `n` pixels. Columns scrolled in
+    /// are blank.
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+
+        for row in self.pixels.chunks_mut(self.width) {
+            let width = row.len();
+            for x in 0..width {
+                let right = match x + n < width {
+                    true => row[x + n] & planes,
+                    false => 0,
+                };
+                row[x] = (row[x] & !planes) | right;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_clears() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(1, 1, 1);
+        fb.resize(HIRES_WIDTH, HIRES_HEIGHT);
+
+        assert!(fb.hires());
+        assert_eq!(fb.rows().count(), HIRES_HEIGHT);
+        assert_eq!(fb.get(1, 1), 0);
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+
+        assert!(!fb.toggle(1, 1, 0b01));
+        assert!(!fb.toggle(1, 1, 0b10));
+        assert_eq!(fb.get(1, 1), 3);
+        assert!(fb.toggle(1, 1, 0b01));
+        assert_eq!(fb.get(1, 1), 2);
+    }
+
+    #[test]
+    fn test_clear_planes() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(1, 1, 3);
+        fb.clear(0b01);
+
+        assert_eq!(fb.get(1, 1), 2);
+    }
+
+    #[test]
+    fn test_scroll_down() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(3, 0, 1);
+        fb.set(3, HEIGHT - 1, 1);
+        fb.scroll_down(2, 0b01);
+
+        assert_eq!(fb.get(3, 0), 0);
+        assert_eq!(fb.get(3, 2), 1);
+        assert_eq!(fb.get(3, HEIGHT - 1), 0);
+    }
+
+    #[test]
+    fn test_scroll_right() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(0, 5, 1);
+        fb.set(WIDTH - 1, 5, 1);
+        fb.scroll_right(4, 0b01);
+
+        assert_eq!(fb.get(4, 5), 1);
+        assert_eq!(fb.get(0, 5), 0);
+        assert_eq!(fb.get(3, 5), 0);
+    }
+
+    #[test]
+    fn test_scroll_left() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(0, 5, 1);
+        fb.set(4, 5, 1);
+        fb.scroll_left(4, 0b01);
+
+        assert_eq!(fb.get(0, 5), 1);
+        assert_eq!(fb.get(WIDTH - 4, 5), 0);
+    }
+
+    #[test]
+    fn test_scroll_only_selected_planes() {
+        let mut fb = Framebuffer::init(WIDTH, HEIGHT);
+        fb.set(0, 0, 3);
+        fb.scroll_right(1, 0b10);
+
+        assert_eq!(fb.get(0, 0), 1);
+        assert_eq!(fb.get(1, 0), 2);
+    }
+}
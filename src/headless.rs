@@ -0,0 +1,278 @@
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::framebuffer::Framebuffer;
+use crate::movie::Movie;
+use crate::profiler::Profiler;
+
+use std::io::{self, Write};
+
+/// Gray levels of the four color indices, matching the SDL frontend.
+const GRAYS: [u8; 4] = [0, 255, 170, 85];
+
+/// Keys to hold down from given frames on.
+///
+/// Each line of a script is a frame number followed by the hex keys held
+/// from that frame until the next line, e.g. `30 5 6`. A frame with no keys
+/// releases them all. `#` starts a comment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyScript {
+    // sorted by frame
+    steps: Vec<(u32, [bool; 16])>,
+}
+
+impl KeyScript {
+    pub fn parse(text: &str) -> Result<KeyScript, String> {
+        let mut steps: Vec<(u32, [bool; 16])> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let frame = match words.next() {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+
+            let frame: u32 = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame {}", frame)))?;
+            if steps.last().is_some_and(|&(last, _)| frame <= last) {
+                return Err(error(format!("frame {} is out of order", frame)));
+            }
+
+            let mut keys = [false; 16];
+            for key in words {
+                let index = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&index| index < 16)
+                    .ok_or_else(|| error(format!("invalid key {}", key)))?;
+                keys[index as usize] = true;
+            }
+            steps.push((frame, keys));
+        }
+
+        Ok(KeyScript { steps })
+    }
+
+    /// Keys held during `frame`.
+    pub fn keys(&self, frame: u32) -> [bool; 16] {
+        self.steps
+            .iter()
+            .take_while(|&&(from, _)| from <= frame)
+            .last()
+            .map_or([false; 16], |&(_, keys)| keys)
+    }
+}
+
+/// Why a headless run stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Finish {
+    /// All frames ran.
+    Frames,
+    /// The program counter reached the address to stop at.
+    Reached,
+    /// The program ran `EXIT`.
+    Exited,
+}
+
+/// Runs a [`Cpu`] without a display, for scripted and CI runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Headless {
+    pub frames: u32,
+    pub ipf: u32,
+    /// Stop before executing the instruction at this address.
+    pub until: Option<u16>,
+    pub script: KeyScript,
+    /// Keys to replay instead of the script; no keys are held past its end.
+    pub movie: Option<Movie>,
+}
+
+impl Headless {
+    pub fn init(frames: u32, ipf: u32) -> Headless {
+        Headless {
+            frames,
+            ipf,
+            until: None,
+            script: KeyScript::default(),
+            movie: None,
+        }
+    }
+
+    /// Keys held during `frame`.
+    pub fn keys(&self, frame: u32) -> [bool; 16] {
+        match &self.movie {
+            Some(movie) => movie.keys(frame as usize).unwrap_or([false; 16]),
+            None => self.script.keys(frame),
+        }
+    }
+
+    /// Runs up to `frames` 60 Hz frames, returning why it stopped and the
+    /// number of whole frames run.
+    pub fn run(&self, cpu: &mut Cpu) -> Result<(Finish, u32), Chip8Error> {
+        self.run_with(cpu, Cpu::cycle)
+    }
+
+    /// Like [`Headless::run`], counting every instruction in `profiler`.
+    pub fn run_profiled(
+        &self,
+        cpu: &mut Cpu,
+        profiler: &mut Profiler,
+    ) -> Result<(Finish, u32), Chip8Error> {
+        self.run_with(cpu, |cpu| profiler.cycle(cpu))
+    }
+
+    fn run_with<F>(&self, cpu: &mut Cpu, mut cycle: F) -> Result<(Finish, u32), Chip8Error>
+    where
+        F: FnMut(&mut Cpu) -> Result<(), Chip8Error>,
+    {
+        for frame in 0..self.frames {
+            cpu.keys = self.keys(frame);
+            for _ in 0..self.ipf {
+                if cpu.exited() {
+                    return Ok((Finish::Exited, frame));
+                }
+                if self.until == Some(cpu.pc()) {
+                    return Ok((Finish::Reached, frame));
+                }
+                cycle(cpu)?;
+            }
+            cpu.tick_timers();
+        }
+
+        Ok((Finish::Frames, self.frames))
+    }
+}
+
+/// SHA-1 of the framebuffer's size and color indices, in hex.
+pub fn hash(pixels: &Framebuffer) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(&(pixels.width() as u16).to_be_bytes());
+    sha1.update(&(pixels.height() as u16).to_be_bytes());
+    for row in pixels.rows() {
+        sha1.update(row);
+    }
+
+    sha1.digest().to_string()
+}
+
+/// Writes the framebuffer as a plain (ASCII) PBM image, with any lit plane
+/// counting as black.
+pub fn write_pbm<W: Write>(pixels: &Framebuffer, mut out: W) -> io::Result<()> {
+    writeln!(out, "P1")?;
+    writeln!(out, "{} {}", pixels.width(), pixels.height())?;
+    for row in pixels.rows() {
+        let bits: Vec<&str> = row
+            .iter()
+            .map(|&pixel| if pixel != 0 { "1" } else { "0" })
+            .collect();
+        writeln!(out, "{}", bits.join(" "))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the framebuffer as a grayscale PNG in the SDL frontend's colors.
+pub fn write_png<W: Write>(pixels: &Framebuffer, out: W) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, pixels.width() as u32, pixels.height() as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = pixels
+        .rows()
+        .flatten()
+        .map(|&pixel| GRAYS[pixel as usize])
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_key_script() {
+        let script = KeyScript::parse("# hold 5, then 4 and 6\n10 5\n20 4 6\n30\n").unwrap();
+
+        assert_eq!(script.keys(0), [false; 16]);
+        assert!(script.keys(15)[5]);
+        assert!(script.keys(20)[4] && script.keys(29)[6] && !script.keys(20)[5]);
+        assert_eq!(script.keys(30), [false; 16]);
+
+        assert!(KeyScript::parse("10 g").is_err());
+        assert!(KeyScript::parse("10\n5").is_err());
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut cpu = Cpu::init(Quirks::default());
+        let mut headless = Headless::init(10, 4);
+        headless.until = Some(0x204);
+
+        assert_eq!(headless.run(&mut cpu), Ok((Finish::Reached, 0)));
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.execute(Instruction::LD_BYTE(0, 30)).unwrap();
+        cpu.execute(Instruction::LD_DT_SET(0)).unwrap();
+        let headless = Headless::init(10, 0);
+
+        assert_eq!(headless.run(&mut cpu), Ok((Finish::Frames, 10)));
+        assert_eq!(cpu.dt(), 20);
+    }
+
+    #[test]
+    fn test_run_profiled() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&[0x12, 0x00]).unwrap();
+        let mut profiler = Profiler::init();
+
+        assert_eq!(
+            Headless::init(3, 4).run_profiled(&mut cpu, &mut profiler),
+            Ok((Finish::Frames, 3))
+        );
+        assert_eq!(profiler.executed(0x200), 12);
+    }
+
+    #[test]
+    fn test_movie_replaces_script() {
+        let mut headless = Headless::init(10, 0);
+        headless.script = KeyScript::parse("0 1").unwrap();
+        let mut movie = Movie::init("", 0, 0, Quirks::default());
+        let mut keys = [false; 16];
+        keys[2] = true;
+        movie.record(&keys);
+        headless.movie = Some(movie);
+
+        assert_eq!(headless.keys(0), keys);
+        assert_eq!(headless.keys(1), [false; 16]);
+    }
+
+    #[test]
+    fn test_write_pbm() {
+        let mut pixels = Framebuffer::init(3, 2);
+        pixels.set(0, 0, 1);
+        pixels.set(2, 1, 2);
+        let mut out = Vec::new();
+        write_pbm(&pixels, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "P1\n3 2\n1 0 0\n0 0 1\n");
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut pixels = Framebuffer::init(64, 32);
+        let blank = hash(&pixels);
+        pixels.set(1, 1, 1);
+
+        assert_eq!(blank.len(), 40);
+        assert_ne!(hash(&pixels), blank);
+    }
+}
@@ -1,113 +1,295 @@
+use crate::config::Keymap;
+
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event::Event::{self, KeyDown, KeyUp};
 use sdl2::keyboard::Keycode;
+use sdl2::GameControllerSubsystem;
+
+use std::collections::{HashMap, HashSet};
+
+/// Stick and trigger travel, out of 32767, ignored as noise around the rest
+/// position.
+const DEADZONE: i16 = 8000;
 
+/// A host key, button or axis direction that can hold a CHIP-8 key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Key(Keycode),
+    Button(Button),
+    // the axis and whether it is the positive direction
+    Axis(Axis, bool),
+}
+
+/// Maps keyboard and game controller events to CHIP-8 keys.
+///
+/// A CHIP-8 key bound to several sources, such as two host keys or the
+/// d-pad and a stick, stays pressed while any of them is held.
 pub struct Input {
+    // CHIP-8 key pressed by each host key
+    keys: HashMap<Keycode, usize>,
+    buttons: HashMap<Button, usize>,
+    // by axis and whether it is the positive direction
+    axes: HashMap<(Axis, bool), usize>,
+    // bound sources currently held
+    held: HashSet<Source>,
 }
 
 impl Input {
-    pub fn init() -> Input {
-        Input {}
+    /// Fails on key, button or axis names SDL doesn't know. Axes are named
+    /// with the direction that presses the key, e.g. `leftx-`; an axis
+    /// without one, such as a trigger, presses when pushed positive.
+    pub fn init(keymap: &Keymap, buttons: &Keymap) -> Result<Input, String> {
+        let keys = keymap
+            .host_keys()
+            .map(|(name, key)| {
+                Keycode::from_name(name)
+                    .map(|keycode| (keycode, key as usize))
+                    .ok_or_else(|| format!("unknown key {}", name))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut input = Input {
+            keys,
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            held: HashSet::new(),
+        };
+        for (name, key) in buttons.host_keys() {
+            let (axis, positive) = match name.strip_suffix('-') {
+                Some(axis) => (axis, false),
+                None => (name.strip_suffix('+').unwrap_or(name), true),
+            };
+            if let Some(button) = Button::from_string(name) {
+                input.buttons.insert(button, key as usize);
+            } else if let Some(axis) = Axis::from_string(axis) {
+                input.axes.insert((axis, positive), key as usize);
+            } else {
+                return Err(format!("unknown button {}", name));
+            }
+        }
+
+        Ok(input)
     }
 
-    pub fn process(&self, keys: &mut [bool; 16], event: Event) {
+    /// Whether `keycode` presses a CHIP-8 key.
+    pub fn binds(&self, keycode: Keycode) -> bool {
+        self.keys.contains_key(&keycode)
+    }
+
+    pub fn process(&mut self, keys: &mut [bool; 16], event: Event) {
         match event {
-            KeyDown { keycode: Some(Keycode::X), .. } => {
-                keys[0] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Num1), .. } => {
-                keys[1] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Num2), .. } => {
-                keys[2] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Num3), .. } => {
-                keys[3] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Q), .. } => {
-                keys[4] = true;
-            }
-            KeyDown { keycode: Some(Keycode::W), .. } => {
-                keys[5] = true;
-            }
-            KeyDown { keycode: Some(Keycode::E), .. } => {
-                keys[6] = true;
-            }
-            KeyDown { keycode: Some(Keycode::A), .. } => {
-                keys[7] = true;
-            }
-            KeyDown { keycode: Some(Keycode::S), .. } => {
-                keys[8] = true;
-            }
-            KeyDown { keycode: Some(Keycode::D), .. } => {
-                keys[9] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Z), .. } => {
-                keys[0xA] = true;
-            }
-            KeyDown { keycode: Some(Keycode::C), .. } => {
-                keys[0xB] = true;
-            }
-            KeyDown { keycode: Some(Keycode::Num4), .. } => {
-                keys[0xC] = true;
+            KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => self.hold(keys, Source::Key(keycode), true),
+            KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => self.hold(keys, Source::Key(keycode), false),
+            Event::ControllerButtonDown { button, .. } => {
+                self.hold(keys, Source::Button(button), true)
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                self.hold(keys, Source::Button(button), false)
+            }
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                self.hold(keys, Source::Axis(axis, true), value > DEADZONE);
+                self.hold(keys, Source::Axis(axis, false), value < -DEADZONE);
             }
-            KeyDown { keycode: Some(Keycode::R), .. } => {
-                keys[0xD] = true;
-            }
-            KeyDown { keycode: Some(Keycode::F), .. } => {
-                keys[0xE] = true;
-            }
-            KeyDown { keycode: Some(Keycode::V), .. } => {
-                keys[0xF] = true;
-            }
-            KeyUp { keycode: Some(Keycode::X), .. } => {
-                keys[0] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Num1), .. } => {
-                keys[1] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Num2), .. } => {
-                keys[2] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Num3), .. } => {
-                keys[3] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Q), .. } => {
-                keys[4] = false;
-            }
-            KeyUp { keycode: Some(Keycode::W), .. } => {
-                keys[5] = false;
-            }
-            KeyUp { keycode: Some(Keycode::E), .. } => {
-                keys[6] = false;
-            }
-            KeyUp { keycode: Some(Keycode::A), .. } => {
-                keys[7] = false;
-            }
-            KeyUp { keycode: Some(Keycode::S), .. } => {
-                keys[8] = false;
-            }
-            KeyUp { keycode: Some(Keycode::D), .. } => {
-                keys[9] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Z), .. } => {
-                keys[0xA] = false;
-            }
-            KeyUp { keycode: Some(Keycode::C), .. } => {
-                keys[0xB] = false;
-            }
-            KeyUp { keycode: Some(Keycode::Num4), .. } => {
-                keys[0xC] = false;
-            }
-            KeyUp { keycode: Some(Keycode::R), .. } => {
-                keys[0xD] = false;
-            }
-            KeyUp { keycode: Some(Keycode::F), .. } => {
-                keys[0xE] = false;
-            }
-            KeyUp { keycode: Some(Keycode::V), .. } => {
-                keys[0xF] = false;
+            _ => {}
+        }
+    }
+
+    /// Forgets every held source, for when `keys` are released elsewhere.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    fn key(&self, source: &Source) -> Option<usize> {
+        match source {
+            Source::Key(keycode) => self.keys.get(keycode),
+            Source::Button(button) => self.buttons.get(button),
+            Source::Axis(axis, positive) => self.axes.get(&(*axis, *positive)),
+        }
+        .copied()
+    }
+
+    /// Updates the key `source` is bound to when it is pressed or released,
+    /// so an axis moving within the deadzone leaves its key alone.
+    fn hold(&mut self, keys: &mut [bool; 16], source: Source, held: bool) {
+        let key = match self.key(&source) {
+            Some(key) => key,
+            None => return,
+        };
+        let changed = match held {
+            true => self.held.insert(source),
+            false => self.held.remove(&source),
+        };
+        if changed {
+            keys[key] = self.held.iter().any(|held| self.key(held) == Some(key));
+        }
+    }
+}
+
+/// Keeps every connected game controller open, since SDL only sends events
+/// for open ones, opening and closing them as they are plugged in and out.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    // by joystick instance id
+    open: HashMap<u32, GameController>,
+}
+
+impl Controllers {
+    /// SDL sends a device added event for each controller connected at
+    /// startup, so they are opened along with hot-plugged ones.
+    pub fn init(subsystem: GameControllerSubsystem) -> Controllers {
+        Controllers {
+            subsystem,
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn process(&mut self, event: &Event) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    println!("Connected {}", controller.name());
+                    self.open.insert(controller.instance_id(), controller);
+                }
+                Err(e) => eprintln!("Can't open controller {}: {}", which, e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.open.remove(&which) {
+                    println!("Disconnected {}", controller.name());
+                }
             }
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::keyboard::Mod;
+
+    fn input() -> Input {
+        Input::init(&Keymap::default(), &Keymap::gamepad()).unwrap()
+    }
+
+    fn button(button: Button, down: bool) -> Event {
+        match down {
+            true => Event::ControllerButtonDown {
+                timestamp: 0,
+                which: 0,
+                button,
+            },
+            false => Event::ControllerButtonUp {
+                timestamp: 0,
+                which: 0,
+                button,
+            },
+        }
+    }
+
+    fn key(keycode: Keycode, down: bool) -> Event {
+        match down {
+            true => Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::NOMOD,
+                repeat: false,
+            },
+            false => Event::KeyUp {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::NOMOD,
+                repeat: false,
+            },
+        }
+    }
+
+    fn axis(axis: Axis, value: i16) -> Event {
+        Event::ControllerAxisMotion {
+            timestamp: 0,
+            which: 0,
+            axis,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_several_keys_for_one() {
+        let mut keymap = Keymap::empty();
+        keymap.bind(5, vec!["Up".to_string(), "W".to_string()]);
+        let mut input = Input::init(&keymap, &Keymap::empty()).unwrap();
+        let mut keys = [false; 16];
+
+        input.process(&mut keys, key(Keycode::Up, true));
+        input.process(&mut keys, key(Keycode::W, true));
+        input.process(&mut keys, key(Keycode::Up, false));
+        assert!(keys[5]);
+        input.process(&mut keys, key(Keycode::W, false));
+        assert!(!keys[5]);
+    }
+
+    #[test]
+    fn test_buttons() {
+        let mut input = input();
+        let mut keys = [false; 16];
+
+        input.process(&mut keys, button(Button::DPadUp, true));
+        input.process(&mut keys, button(Button::A, true));
+        assert!(keys[2] && keys[5]);
+
+        input.process(&mut keys, button(Button::DPadUp, false));
+        assert!(!keys[2] && keys[5]);
+
+        input.process(&mut keys, button(Button::Guide, true));
+        assert_eq!(keys.iter().filter(|&&held| held).count(), 1);
+    }
+
+    #[test]
+    fn test_axes() {
+        let mut input = input();
+        let mut keys = [false; 16];
+
+        input.process(&mut keys, axis(Axis::LeftX, DEADZONE - 1));
+        assert!(!keys[6]);
+        input.process(&mut keys, axis(Axis::LeftX, i16::MAX));
+        assert!(keys[6] && !keys[4]);
+        input.process(&mut keys, axis(Axis::LeftX, i16::MIN));
+        assert!(!keys[6] && keys[4]);
+        input.process(&mut keys, axis(Axis::LeftX, 0));
+        assert!(!keys[6] && !keys[4]);
+    }
+
+    #[test]
+    fn test_axis_in_deadzone_keeps_dpad() {
+        let mut input = input();
+        let mut keys = [false; 16];
+
+        input.process(&mut keys, button(Button::DPadUp, true));
+        input.process(&mut keys, axis(Axis::LeftY, -100));
+        assert!(keys[2]);
+
+        // the stick holds the key too, until both let go
+        input.process(&mut keys, axis(Axis::LeftY, i16::MIN));
+        input.process(&mut keys, button(Button::DPadUp, false));
+        assert!(keys[2]);
+        input.process(&mut keys, axis(Axis::LeftY, 0));
+        assert!(!keys[2]);
+    }
+
+    #[test]
+    fn test_unknown_button() {
+        let mut buttons = Keymap::empty();
+        buttons.bind(5, vec!["turbo".to_string()]);
+
+        assert!(Input::init(&Keymap::default(), &buttons).is_err());
+    }
+}
@@ -1,5 +1,14 @@
+use crate::error::Chip8Error;
+
+use std::fmt;
+
+/// A decoded CHIP-8 instruction.
+///
+/// Variants follow the mnemonics of Cowgod's technical reference, including
+/// its SUPER-CHIP 1.1 section, and of Octo for XO-CHIP. Register operands
+/// hold the register index (`0x0` to `0xF`), not its value.
 #[derive(Debug, PartialEq)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Instruction {
     SYS(u16),
     CLS,
@@ -17,9 +26,9 @@ pub enum Instruction {
     XOR(u16, u16),
     ADD(u16, u16),
     SUB(u16, u16),
-    SHR(u16),
+    SHR(u16, u16),
     SUBN(u16, u16),
-    SHL(u16),
+    SHL(u16, u16),
     SNE(u16, u16),
     LD_I(u16),
     JP_V0(u16),
@@ -36,6 +45,23 @@ pub enum Instruction {
     LD_B(u16),
     LD_STORE_I(u16),
     LD_READ_I(u16),
+    // SUPER-CHIP 1.1
+    SCD(u8),
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
+    LD_HF(u16),
+    LD_STORE_R(u16),
+    LD_READ_R(u16),
+    // XO-CHIP
+    SAVE(u16, u16),
+    LOAD(u16, u16),
+    LD_I_LONG(u16),
+    PLANE(u8),
+    AUDIO,
+    PITCH(u16),
 }
 
 impl Instruction {
@@ -53,10 +79,28 @@ impl Instruction {
         (vx, vy)
     }
 
-    pub fn parse(bytes: u16) -> Result<Instruction, String> {
+    /// Decodes a big-endian instruction word.
+    ///
+    /// A bare word has no address, so an [`Chip8Error::InvalidOpcode`]
+    /// returned from here always has a `pc` of 0.
+    ///
+    /// XO-CHIP's `F000 NNNN` is the only instruction spanning two words. It
+    /// decodes to `LD_I_LONG(0)`; the caller reads the address from the
+    /// following word.
+    pub fn parse(bytes: u16) -> Result<Instruction, Chip8Error> {
+        let invalid = Chip8Error::InvalidOpcode {
+            pc: 0,
+            opcode: bytes,
+        };
         let parsed = match bytes {
             0x00E0 => Instruction::CLS,
             0x00EE => Instruction::RET,
+            0x00C0..=0x00CF => Instruction::SCD((bytes & 0x000F) as u8),
+            0x00FB => Instruction::SCR,
+            0x00FC => Instruction::SCL,
+            0x00FD => Instruction::EXIT,
+            0x00FE => Instruction::LOW,
+            0x00FF => Instruction::HIGH,
             0x0000..=0x0FFF => Instruction::SYS(bytes & 0x0FFF),
             0x1000..=0x1FFF => Instruction::JP_ADDR(bytes & 0x0FFF),
             0x2000..=0x2FFF => Instruction::CALL_ADDR(bytes & 0x0FFF),
@@ -68,9 +112,16 @@ impl Instruction {
                 let (vx, arg) = Instruction::parse_xkk(bytes);
                 Instruction::SNE_BYTE(vx, arg)
             }
-            0x5000..=0x5FF0 => {
+            0x5000..=0x5FFF => {
                 let (vx, vy) = Instruction::parse_xy(bytes);
-                Instruction::SE(vx, vy)
+                let opcode = bytes & 0x000F;
+
+                match opcode {
+                    0x0 => Instruction::SE(vx, vy),
+                    0x2 => Instruction::SAVE(vx, vy),
+                    0x3 => Instruction::LOAD(vx, vy),
+                    _ => return Err(invalid),
+                }
             }
             0x6000..=0x6FFF => {
                 let (vx, kk) = Instruction::parse_xkk(bytes);
@@ -80,7 +131,7 @@ impl Instruction {
                 let (vx, kk) = Instruction::parse_xkk(bytes);
                 Instruction::ADD_BYTE(vx, kk)
             }
-            0x8000..=0x8FFE => {
+            0x8000..=0x8FFF => {
                 let (vx, vy) = Instruction::parse_xy(bytes);
                 let opcode = bytes & 0x000F;
 
@@ -91,13 +142,13 @@ impl Instruction {
                     0x3 => Instruction::XOR(vx, vy),
                     0x4 => Instruction::ADD(vx, vy),
                     0x5 => Instruction::SUB(vx, vy),
-                    0x6 => Instruction::SHR(vx),
+                    0x6 => Instruction::SHR(vx, vy),
                     0x7 => Instruction::SUBN(vx, vy),
-                    0xE => Instruction::SHL(vx),
-                    _ => panic!("Unhandled 8xy{:x?}", opcode),
+                    0xE => Instruction::SHL(vx, vy),
+                    _ => return Err(invalid),
                 }
             }
-            0x9000..=0x9FF0 => {
+            0x9000..=0x9FFF if bytes & 0x000F == 0 => {
                 let (vx, vy) = Instruction::parse_xy(bytes);
                 Instruction::SNE(vx, vy)
             }
@@ -114,36 +165,175 @@ impl Instruction {
 
                 Instruction::DRW(vx, vy, n)
             }
-            0xE09E..=0xEF9E => {
+            0xE000..=0xEFFF => {
                 let (vx, _) = Instruction::parse_xy(bytes);
-                Instruction::SKP(vx)
-            }
-            0xE0A1..=0xEFA1 => {
-                let (vx, _) = Instruction::parse_xy(bytes);
-                Instruction::SKNP(vx)
+
+                match bytes & 0x00FF {
+                    0x9E => Instruction::SKP(vx),
+                    0xA1 => Instruction::SKNP(vx),
+                    _ => return Err(invalid),
+                }
             }
-            0xF007..=0xFF65 => {
+            0xF000..=0xFFFF => {
                 let (vx, _) = Instruction::parse_xy(bytes);
                 let opcode = bytes & 0x00FF;
 
                 match opcode {
+                    0x00 if vx == 0 => Instruction::LD_I_LONG(0),
+                    0x01 => Instruction::PLANE(vx as u8),
+                    0x02 if vx == 0 => Instruction::AUDIO,
                     0x07 => Instruction::LD_DT(vx),
                     0x0A => Instruction::LD_KEY(vx),
                     0x15 => Instruction::LD_DT_SET(vx),
                     0x18 => Instruction::LD_ST_SET(vx),
                     0x1E => Instruction::ADD_I(vx),
                     0x29 => Instruction::LD_F(vx),
+                    0x30 => Instruction::LD_HF(vx),
                     0x33 => Instruction::LD_B(vx),
+                    0x3A => Instruction::PITCH(vx),
                     0x55 => Instruction::LD_STORE_I(vx),
                     0x65 => Instruction::LD_READ_I(vx),
-                    _ => panic!("Unhandled fx{:x?}", opcode),
+                    0x75 => Instruction::LD_STORE_R(vx),
+                    0x85 => Instruction::LD_READ_R(vx),
+                    _ => return Err(invalid),
                 }
             }
-            _ => return Err(format!("Unable to parse {:x}", bytes)),
+            _ => return Err(invalid),
         };
 
         Ok(parsed)
     }
+
+    /// Encodes the instruction back into its big-endian word, the inverse of
+    /// [`Instruction::parse`].
+    ///
+    /// Operands are masked to the width of their field. `LD_I_LONG` encodes
+    /// to `0xF000` alone; its address goes in the word that follows.
+    pub fn encode(&self) -> u16 {
+        let xkk = |vx: u16, kk: u8| (vx & 0xF) << 8 | kk as u16;
+        let xyn = |vx: u16, vy: u16, n: u16| (vx & 0xF) << 8 | (vy & 0xF) << 4 | (n & 0xF);
+        let x = |vx: u16| (vx & 0xF) << 8;
+
+        match *self {
+            Instruction::SYS(addr) => addr & 0x0FFF,
+            Instruction::CLS => 0x00E0,
+            Instruction::RET => 0x00EE,
+            Instruction::JP_ADDR(addr) => 0x1000 | addr & 0x0FFF,
+            Instruction::CALL_ADDR(addr) => 0x2000 | addr & 0x0FFF,
+            Instruction::SE_BYTE(vx, kk) => 0x3000 | xkk(vx, kk),
+            Instruction::SNE_BYTE(vx, kk) => 0x4000 | xkk(vx, kk),
+            Instruction::SE(vx, vy) => 0x5000 | xyn(vx, vy, 0x0),
+            Instruction::LD_BYTE(vx, kk) => 0x6000 | xkk(vx, kk),
+            Instruction::ADD_BYTE(vx, kk) => 0x7000 | xkk(vx, kk),
+            Instruction::LD(vx, vy) => 0x8000 | xyn(vx, vy, 0x0),
+            Instruction::OR(vx, vy) => 0x8000 | xyn(vx, vy, 0x1),
+            Instruction::AND(vx, vy) => 0x8000 | xyn(vx, vy, 0x2),
+            Instruction::XOR(vx, vy) => 0x8000 | xyn(vx, vy, 0x3),
+            Instruction::ADD(vx, vy) => 0x8000 | xyn(vx, vy, 0x4),
+            Instruction::SUB(vx, vy) => 0x8000 | xyn(vx, vy, 0x5),
+            Instruction::SHR(vx, vy) => 0x8000 | xyn(vx, vy, 0x6),
+            Instruction::SUBN(vx, vy) => 0x8000 | xyn(vx, vy, 0x7),
+            Instruction::SHL(vx, vy) => 0x8000 | xyn(vx, vy, 0xE),
+            Instruction::SNE(vx, vy) => 0x9000 | xyn(vx, vy, 0x0),
+            Instruction::LD_I(addr) => 0xA000 | addr & 0x0FFF,
+            Instruction::JP_V0(addr) => 0xB000 | addr & 0x0FFF,
+            Instruction::RND_BYTE(vx, kk) => 0xC000 | xkk(vx, kk),
+            Instruction::DRW(vx, vy, n) => 0xD000 | xyn(vx, vy, n as u16),
+            Instruction::SKP(vx) => 0xE09E | x(vx),
+            Instruction::SKNP(vx) => 0xE0A1 | x(vx),
+            Instruction::LD_DT(vx) => 0xF007 | x(vx),
+            Instruction::LD_KEY(vx) => 0xF00A | x(vx),
+            Instruction::LD_DT_SET(vx) => 0xF015 | x(vx),
+            Instruction::LD_ST_SET(vx) => 0xF018 | x(vx),
+            Instruction::ADD_I(vx) => 0xF01E | x(vx),
+            Instruction::LD_F(vx) => 0xF029 | x(vx),
+            Instruction::LD_B(vx) => 0xF033 | x(vx),
+            Instruction::LD_STORE_I(vx) => 0xF055 | x(vx),
+            Instruction::LD_READ_I(vx) => 0xF065 | x(vx),
+            Instruction::SCD(n) => 0x00C0 | (n & 0xF) as u16,
+            Instruction::SCR => 0x00FB,
+            Instruction::SCL => 0x00FC,
+            Instruction::EXIT => 0x00FD,
+            Instruction::LOW => 0x00FE,
+            Instruction::HIGH => 0x00FF,
+            Instruction::LD_HF(vx) => 0xF030 | x(vx),
+            Instruction::LD_STORE_R(vx) => 0xF075 | x(vx),
+            Instruction::LD_READ_R(vx) => 0xF085 | x(vx),
+            Instruction::SAVE(vx, vy) => 0x5000 | xyn(vx, vy, 0x2),
+            Instruction::LOAD(vx, vy) => 0x5000 | xyn(vx, vy, 0x3),
+            Instruction::LD_I_LONG(_) => 0xF000,
+            Instruction::PLANE(n) => 0xF001 | x(n as u16),
+            Instruction::AUDIO => 0xF002,
+            Instruction::PITCH(vx) => 0xF03A | x(vx),
+        }
+    }
+
+    /// Size of the instruction in memory, in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LD_I_LONG(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Formats the instruction in Cowgod's assembly syntax, e.g. `LD V1, 0x23`
+/// or `DRW V0, V1, 5`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::SYS(addr) => write!(f, "SYS {:#05x}", addr),
+            Instruction::CLS => write!(f, "CLS"),
+            Instruction::RET => write!(f, "RET"),
+            Instruction::JP_ADDR(addr) => write!(f, "JP {:#05x}", addr),
+            Instruction::CALL_ADDR(addr) => write!(f, "CALL {:#05x}", addr),
+            Instruction::SE_BYTE(vx, kk) => write!(f, "SE V{:X}, {:#04x}", vx, kk),
+            Instruction::SNE_BYTE(vx, kk) => write!(f, "SNE V{:X}, {:#04x}", vx, kk),
+            Instruction::SE(vx, vy) => write!(f, "SE V{:X}, V{:X}", vx, vy),
+            Instruction::LD_BYTE(vx, kk) => write!(f, "LD V{:X}, {:#04x}", vx, kk),
+            Instruction::ADD_BYTE(vx, kk) => write!(f, "ADD V{:X}, {:#04x}", vx, kk),
+            Instruction::LD(vx, vy) => write!(f, "LD V{:X}, V{:X}", vx, vy),
+            Instruction::OR(vx, vy) => write!(f, "OR V{:X}, V{:X}", vx, vy),
+            Instruction::AND(vx, vy) => write!(f, "AND V{:X}, V{:X}", vx, vy),
+            Instruction::XOR(vx, vy) => write!(f, "XOR V{:X}, V{:X}", vx, vy),
+            Instruction::ADD(vx, vy) => write!(f, "ADD V{:X}, V{:X}", vx, vy),
+            Instruction::SUB(vx, vy) => write!(f, "SUB V{:X}, V{:X}", vx, vy),
+            Instruction::SHR(vx, vy) => write!(f, "SHR V{:X}, V{:X}", vx, vy),
+            Instruction::SUBN(vx, vy) => write!(f, "SUBN V{:X}, V{:X}", vx, vy),
+            Instruction::SHL(vx, vy) => write!(f, "SHL V{:X}, V{:X}", vx, vy),
+            Instruction::SNE(vx, vy) => write!(f, "SNE V{:X}, V{:X}", vx, vy),
+            Instruction::LD_I(addr) => write!(f, "LD I, {:#05x}", addr),
+            Instruction::JP_V0(addr) => write!(f, "JP V0, {:#05x}", addr),
+            Instruction::RND_BYTE(vx, kk) => write!(f, "RND V{:X}, {:#04x}", vx, kk),
+            Instruction::DRW(vx, vy, n) => write!(f, "DRW V{:X}, V{:X}, {}", vx, vy, n),
+            Instruction::SKP(vx) => write!(f, "SKP V{:X}", vx),
+            Instruction::SKNP(vx) => write!(f, "SKNP V{:X}", vx),
+            Instruction::LD_DT(vx) => write!(f, "LD V{:X}, DT", vx),
+            Instruction::LD_KEY(vx) => write!(f, "LD V{:X}, K", vx),
+            Instruction::LD_DT_SET(vx) => write!(f, "LD DT, V{:X}", vx),
+            Instruction::LD_ST_SET(vx) => write!(f, "LD ST, V{:X}", vx),
+            Instruction::ADD_I(vx) => write!(f, "ADD I, V{:X}", vx),
+            Instruction::LD_F(vx) => write!(f, "LD F, V{:X}", vx),
+            Instruction::LD_B(vx) => write!(f, "LD B, V{:X}", vx),
+            Instruction::LD_STORE_I(vx) => write!(f, "LD [I], V{:X}", vx),
+            Instruction::LD_READ_I(vx) => write!(f, "LD V{:X}, [I]", vx),
+            Instruction::SCD(n) => write!(f, "SCD {}", n),
+            Instruction::SCR => write!(f, "SCR"),
+            Instruction::SCL => write!(f, "SCL"),
+            Instruction::EXIT => write!(f, "EXIT"),
+            Instruction::LOW => write!(f, "LOW"),
+            Instruction::HIGH => write!(f, "HIGH"),
+            Instruction::LD_HF(vx) => write!(f, "LD HF, V{:X}", vx),
+            Instruction::LD_STORE_R(vx) => write!(f, "LD R, V{:X}", vx),
+            Instruction::LD_READ_R(vx) => write!(f, "LD V{:X}, R", vx),
+            Instruction::SAVE(vx, vy) => write!(f, "SAVE V{:X}, V{:X}", vx, vy),
+            Instruction::LOAD(vx, vy) => write!(f, "LOAD V{:X}, V{:X}", vx, vy),
+            Instruction::LD_I_LONG(addr) => write!(f, "LD I, LONG {:#06x}", addr),
+            Instruction::PLANE(n) => write!(f, "PLANE {}", n),
+            Instruction::AUDIO => write!(f, "AUDIO"),
+            Instruction::PITCH(vx) => write!(f, "PITCH V{:X}", vx),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -209,4 +399,130 @@ mod tests {
             Ok(Instruction::OR(0x0007, 0x000B))
         )
     }
+
+    #[test]
+    fn test_parse_shr() {
+        assert_eq!(
+            Instruction::parse(0x8126),
+            Ok(Instruction::SHR(0x0001, 0x0002))
+        )
+    }
+
+    #[test]
+    fn test_parse_scd() {
+        assert_eq!(Instruction::parse(0x00C5), Ok(Instruction::SCD(5)))
+    }
+
+    #[test]
+    fn test_parse_schip_display() {
+        assert_eq!(Instruction::parse(0x00FB), Ok(Instruction::SCR));
+        assert_eq!(Instruction::parse(0x00FC), Ok(Instruction::SCL));
+        assert_eq!(Instruction::parse(0x00FD), Ok(Instruction::EXIT));
+        assert_eq!(Instruction::parse(0x00FE), Ok(Instruction::LOW));
+        assert_eq!(Instruction::parse(0x00FF), Ok(Instruction::HIGH));
+    }
+
+    #[test]
+    fn test_parse_rpl() {
+        assert_eq!(
+            Instruction::parse(0xFF75),
+            Ok(Instruction::LD_STORE_R(0x000F))
+        );
+        assert_eq!(
+            Instruction::parse(0xF385),
+            Ok(Instruction::LD_READ_R(0x0003))
+        );
+    }
+
+    #[test]
+    fn test_parse_save_load() {
+        assert_eq!(
+            Instruction::parse(0x5122),
+            Ok(Instruction::SAVE(0x0001, 0x0002))
+        );
+        assert_eq!(
+            Instruction::parse(0x5213),
+            Ok(Instruction::LOAD(0x0002, 0x0001))
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown_5xyn() {
+        assert!(Instruction::parse(0x5121).is_err());
+    }
+
+    #[test]
+    fn test_parse_xo_chip_fxnn() {
+        assert_eq!(Instruction::parse(0xF000), Ok(Instruction::LD_I_LONG(0)));
+        assert_eq!(Instruction::parse(0xF201), Ok(Instruction::PLANE(2)));
+        assert_eq!(Instruction::parse(0xF002), Ok(Instruction::AUDIO));
+        assert_eq!(Instruction::parse(0xF43A), Ok(Instruction::PITCH(0x0004)));
+        assert!(Instruction::parse(0xF100).is_err());
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(Instruction::LD_I_LONG(0x1234).size(), 4);
+        assert_eq!(Instruction::CLS.size(), 2);
+    }
+
+    #[test]
+    fn test_parse_unknown_8xyn() {
+        assert_eq!(
+            Instruction::parse(0x8128),
+            Err(Chip8Error::InvalidOpcode {
+                pc: 0,
+                opcode: 0x8128
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_unknown_9xyn_exnn() {
+        for word in [0x9121, 0x9FFF, 0xE1A0, 0xE59F, 0xEF00] {
+            assert_eq!(
+                Instruction::parse(word),
+                Err(Chip8Error::InvalidOpcode {
+                    pc: 0,
+                    opcode: word
+                })
+            );
+        }
+        // inside the range 0xE09E..=0xEF9E that used to decode to SKP
+        assert_eq!(Instruction::parse(0xE1A1), Ok(Instruction::SKNP(1)));
+    }
+
+    #[test]
+    fn test_parse_unknown_fxnn() {
+        assert_eq!(
+            Instruction::parse(0xF1FF),
+            Err(Chip8Error::InvalidOpcode {
+                pc: 0,
+                opcode: 0xF1FF
+            })
+        )
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Instruction::LD_BYTE(1, 0x23).to_string(), "LD V1, 0x23");
+        assert_eq!(Instruction::DRW(0, 1, 5).to_string(), "DRW V0, V1, 5");
+        assert_eq!(Instruction::JP_ADDR(0x200).to_string(), "JP 0x200");
+        assert_eq!(Instruction::LD_READ_I(0xA).to_string(), "LD VA, [I]");
+        assert_eq!(
+            Instruction::LD_I_LONG(0x1234).to_string(),
+            "LD I, LONG 0x1234"
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trips() {
+        for word in 0..=0xFFFF {
+            if let Ok(inst) = Instruction::parse(word) {
+                assert_eq!(inst.encode(), word, "{}", inst);
+            }
+        }
+        assert_eq!(Instruction::DRW(0, 1, 5).encode(), 0xD015);
+        assert_eq!(Instruction::SKNP(0xA).encode(), 0xEAA1);
+    }
 }
@@ -0,0 +1,52 @@
+//! A CHIP-8 emulator core.
+//!
+//! This crate contains everything needed to run a CHIP-8 program without
+//! tying it to any particular frontend: the [`Cpu`] holding the machine state,
+//! the [`Instruction`] decoder, the ROM loader and a database of known ROMs.
+//! Frontends (such as the SDL binary shipped with this crate) drive the
+//! [`Cpu`] and read back its framebuffer and key state.
+//!
+//! ```no_run
+//! use chip8::rom::Rom;
+//! use chip8::{Cpu, Quirks};
+//!
+//! # fn main() -> Result<(), chip8::rom::RomError> {
+//! let mut cpu = Cpu::init(Quirks::default());
+//! cpu.load_rom(&Rom::read("roms/test_opcode.ch8")?)?;
+//! loop {
+//!     if let Err(error) = cpu.cycle() {
+//!         eprintln!("{}", error);
+//!         break;
+//!     }
+//!     if cpu.should_draw {
+//!         // present cpu.pixels
+//!         cpu.should_draw = false;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod asm;
+pub mod beeper;
+pub mod cpu;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod framebuffer;
+pub mod headless;
+pub mod instruction;
+pub mod movie;
+pub mod profiler;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod state;
+
+pub use crate::cpu::Cpu;
+pub use crate::error::Chip8Error;
+pub use crate::framebuffer::Framebuffer;
+pub use crate::instruction::Instruction;
+pub use crate::quirks::Quirks;
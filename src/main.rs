@@ -1,21 +1,243 @@
+#[cfg(feature = "sdl")]
+mod audio;
+#[cfg(feature = "sdl")]
 mod chip8;
-mod cpu;
+#[cfg_attr(not(any(feature = "sdl", feature = "tui")), allow(dead_code))]
+mod config;
+#[cfg(feature = "sdl")]
 mod display;
+#[cfg(feature = "sdl")]
 mod input;
-mod instruction;
-mod rom;
+mod options;
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+mod palette;
+#[cfg(feature = "tui")]
+mod terminal;
 
-use crate::chip8::Chip8;
+use crate::config::Config;
+use crate::options::Options;
+use ::chip8::cpu::START_ADDRESS;
+use ::chip8::database::{Database, RomInfo};
+use ::chip8::headless::{self, Finish, Headless, KeyScript};
+use ::chip8::movie::Movie;
+use ::chip8::profiler::Profiler;
+use ::chip8::rom::Rom;
+use ::chip8::Cpu;
 use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process;
 
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options::parse(&args).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
 
-    let filename = &args[1];
+    let movie = match &options.play {
+        Some(path) => Some(read_movie(path)?),
+        None => None,
+    };
+    if let Some(movie) = &movie {
+        options.load_address = movie.load_address;
+        options.start_address = movie.start_address;
+        // it was only recorded if the address was allowed
+        options.low_memory = movie.load_address < START_ADDRESS;
+    }
 
-    let mut chip8 = Chip8::init();
-    chip8.load(filename);
-    chip8.start();
+    let rom = read_rom(&options)?;
+    let info = match (options.database, &options.database_file) {
+        (true, Some(path)) => Database::read(path)?.lookup(rom.hash()).cloned(),
+        (true, None) => Database::bundled().lookup(rom.hash()).cloned(),
+        (false, _) => None,
+    };
+    if let Some(info) = &info {
+        // headless output is only the hash of the screen
+        if !options.headless {
+            println!("{}", describe(info));
+        }
+        options.apply_rom_info(info);
+    }
+    // a movie replays with the settings it was recorded with
+    if let Some(movie) = &movie {
+        options.quirks = movie.quirks;
+        options.ipf = movie.ipf;
+    }
 
-    Ok(())
+    if options.headless {
+        run_headless(&options, &rom, movie)
+    } else if options.tui {
+        run_terminal(&options, &rom, info.as_ref())
+    } else {
+        run_window(&options, &rom, info.as_ref(), movie)
+    }
+}
+
+/// The title, authors and platform of a rom found in the database.
+fn describe(info: &RomInfo) -> String {
+    let mut description = info.title.clone();
+    if !info.authors.is_empty() {
+        description += &format!(" by {}", info.authors.join(", "));
+    }
+
+    format!("{} ({})", description, info.platform)
+}
+
+/// Reads the rom and places it at the addresses given on the command line.
+fn read_rom(options: &Options) -> Result<Rom, String> {
+    let mut rom = Rom::read(&options.rom).map_err(|e| e.to_string())?;
+    rom.load_address = options.load_address;
+    rom.start_address = options.start_address;
+    rom.low_memory = options.low_memory;
+    rom.check().map_err(|e| format!("{}: {}", options.rom, e))?;
+
+    Ok(rom)
+}
+
+fn read_movie(path: &str) -> Result<Movie, String> {
+    fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|movie| Movie::decode(&movie).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(feature = "sdl")]
+fn run_window(
+    options: &Options,
+    rom: &Rom,
+    info: Option<&RomInfo>,
+    movie: Option<Movie>,
+) -> Result<(), String> {
+    let config = load_config(options, info)?;
+    let mut chip8 = crate::chip8::Chip8::init(options, movie, config);
+    chip8.load(rom)?;
+    chip8.start().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_window(_: &Options, _: &Rom, _: Option<&RomInfo>, _: Option<Movie>) -> Result<(), String> {
+    Err("built without SDL, use --tui or --headless".to_string())
+}
+
+#[cfg(feature = "tui")]
+fn run_terminal(options: &Options, rom: &Rom, info: Option<&RomInfo>) -> Result<(), String> {
+    let mut tui = crate::terminal::Tui::init(options, load_config(options, info)?);
+    tui.load(rom)?;
+    tui.start()
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_terminal(_: &Options, _: &Rom, _: Option<&RomInfo>) -> Result<(), String> {
+    Err("built without the terminal frontend".to_string())
+}
+
+/// Reads `--config`, or the default config file if there is one, with the
+/// key hints of the rom's database entry.
+#[cfg_attr(not(any(feature = "sdl", feature = "tui")), allow(dead_code))]
+fn load_config(options: &Options, info: Option<&RomInfo>) -> Result<Config, String> {
+    let path = options
+        .config
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(Config::path);
+    let mut config = match path {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    config.display.apply(&options.display)?;
+    if let Some(info) = info {
+        config.hint(&info.keys);
+    }
+
+    Ok(config)
+}
+
+/// Writes the report of `profiler` to `path` and its call stacks to
+/// `path.folded`.
+fn save_profile(profiler: &Profiler, cpu: &Cpu, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    profiler
+        .write_report(cpu, BufWriter::new(file))
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let folded = format!("{}.folded", path);
+    let file = File::create(&folded).map_err(|e| format!("{}: {}", folded, e))?;
+    profiler
+        .write_folded(BufWriter::new(file))
+        .map_err(|e| format!("{}: {}", folded, e))
+}
+
+/// Runs the ROM without a window, optionally saves the screen and prints its
+/// hash.
+///
+/// Unless given, the seed is 0 so that runs are reproducible.
+fn run_headless(options: &Options, rom: &Rom, movie: Option<Movie>) -> Result<(), String> {
+    let mut cpu = Cpu::init(options.quirks);
+    cpu.load_rom(rom).map_err(|e| e.to_string())?;
+
+    let mut runner = Headless::init(options.frames, options.ipf);
+    runner.until = options.until;
+    if let Some(path) = &options.keys {
+        let script = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        runner.script = KeyScript::parse(&script).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    let seed = match &movie {
+        Some(movie) if movie.rom_hash != cpu.rom_hash() => {
+            return Err(format!(
+                "movie is for rom {}, not the loaded rom {}",
+                movie.rom_hash,
+                cpu.rom_hash()
+            ));
+        }
+        Some(movie) => {
+            runner.frames = movie.len() as u32;
+            movie.seed
+        }
+        None => options.seed.unwrap_or(0),
+    };
+    runner.movie = movie;
+    cpu.reseed(seed);
+    let mut profiler = options.profile.as_ref().map(|_| Profiler::init());
+    let (finish, frames) = match &mut profiler {
+        Some(profiler) => runner.run_profiled(&mut cpu, profiler),
+        None => runner.run(&mut cpu),
+    }
+    .map_err(|e| e.to_string())?;
+
+    if let (Some(profiler), Some(path)) = (&profiler, &options.profile) {
+        save_profile(profiler, &cpu, path)?;
+    }
+
+    if let Some(path) = &options.record {
+        let mut movie = Movie::init(cpu.rom_hash(), seed, options.ipf, options.quirks);
+        movie.load_address = rom.load_address;
+        movie.start_address = rom.start_address;
+        // only the frames that ran, if --until or EXIT cut the run short
+        for frame in 0..frames {
+            movie.record(&runner.keys(frame));
+        }
+        fs::write(path, movie.encode()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let written = if path.ends_with(".png") {
+            headless::write_png(&cpu.pixels, BufWriter::new(file))
+        } else if path.ends_with(".pbm") {
+            headless::write_pbm(&cpu.pixels, BufWriter::new(file))
+        } else {
+            return Err(format!("{}: screenshots must be .png or .pbm", path));
+        };
+        written.map_err(|e| format!("{}: {}", path, e))?;
+    }
+    println!("{}", headless::hash(&cpu.pixels));
+
+    match options.until {
+        Some(addr) if finish != Finish::Reached => {
+            Err(format!("{:#05x} not reached after {} frames", addr, frames))
+        }
+        _ => Ok(()),
+    }
 }
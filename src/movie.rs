@@ -0,0 +1,284 @@
+use crate::cpu::START_ADDRESS;
+use crate::quirks::Quirks;
+
+use std::error::Error;
+use std::fmt;
+
+/// Identifies a movie file.
+pub const MAGIC: &[u8; 4] = b"C8MV";
+/// Version of the movie format written by [`Movie::encode`].
+pub const VERSION: u16 = 1;
+
+// magic, version, hex SHA-1 of the rom, seed, ipf, quirks, load and start
+// addresses and frame count
+const HEADER_SIZE: usize = 4 + 2 + 40 + 8 + 4 + 1 + 4 + 4;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Errors raised while reading a movie.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MovieError {
+    /// The data does not start with [`MAGIC`].
+    NotAMovie,
+    /// A format version this build cannot read.
+    UnsupportedVersion(u16),
+    /// The checksum does not match or the data ends early.
+    Corrupt,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::Corrupt => write!(f, "movie is corrupt"),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+/// A recording of the keys held on every frame of a run, along with
+/// everything else that decides how the run plays out: the ROM and where it
+/// was loaded, the seed of `RND`, the instructions per frame and the quirks.
+///
+/// Replaying a movie into a [`crate::Cpu`] created with the same quirks,
+/// loaded with the same ROM at the same addresses and reseeded with `seed`
+/// reproduces the recorded run exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: String,
+    pub seed: u64,
+    pub ipf: u32,
+    pub quirks: Quirks,
+    /// Where the ROM was loaded, as in [`crate::rom::Rom`].
+    pub load_address: u16,
+    /// Where execution started.
+    pub start_address: u16,
+    // one bit per key, key 0 in the lowest bit
+    frames: Vec<u16>,
+}
+
+fn quirk_bits(quirks: &Quirks) -> u8 {
+    [
+        quirks.vf_reset,
+        quirks.increment_i,
+        quirks.shift_vx,
+        quirks.jump_vx,
+        quirks.clip_sprites,
+    ]
+    .iter()
+    .enumerate()
+    .map(|(bit, &on)| (on as u8) << bit)
+    .sum()
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |bit: u8| bits & 1 << bit != 0;
+    Quirks {
+        vf_reset: on(0),
+        increment_i: on(1),
+        shift_vx: on(2),
+        jump_vx: on(3),
+        clip_sprites: on(4),
+    }
+}
+
+impl Movie {
+    /// Starts an empty movie of the ROM hashing to `rom_hash`, loaded and
+    /// started at [`START_ADDRESS`].
+    pub fn init(rom_hash: &str, seed: u64, ipf: u32, quirks: Quirks) -> Movie {
+        Movie {
+            rom_hash: rom_hash.to_string(),
+            seed,
+            ipf,
+            quirks,
+            load_address: START_ADDRESS,
+            start_address: START_ADDRESS,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Appends a frame with `keys` held.
+    pub fn record(&mut self, keys: &[bool; 16]) {
+        let bits = keys
+            .iter()
+            .enumerate()
+            .map(|(key, &held)| (held as u16) << key)
+            .sum();
+        self.frames.push(bits);
+    }
+
+    /// Keys held on `frame`, or `None` past the end of the movie.
+    pub fn keys(&self, frame: usize) -> Option<[bool; 16]> {
+        let bits = *self.frames.get(frame)?;
+        let mut keys = [false; 16];
+        for (key, held) in keys.iter_mut().enumerate() {
+            *held = bits & 1 << key != 0;
+        }
+
+        Some(keys)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut movie = Vec::with_capacity(HEADER_SIZE + self.frames.len() * 2 + CHECKSUM_SIZE);
+        movie.extend_from_slice(MAGIC);
+        movie.extend_from_slice(&VERSION.to_be_bytes());
+        movie.extend_from_slice(self.rom_hash.as_bytes());
+        movie.extend_from_slice(&self.seed.to_be_bytes());
+        movie.extend_from_slice(&self.ipf.to_be_bytes());
+        movie.push(quirk_bits(&self.quirks));
+        movie.extend_from_slice(&self.load_address.to_be_bytes());
+        movie.extend_from_slice(&self.start_address.to_be_bytes());
+        movie.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for frame in &self.frames {
+            movie.extend_from_slice(&frame.to_be_bytes());
+        }
+
+        let checksum = crc32fast::hash(&movie);
+        movie.extend_from_slice(&checksum.to_be_bytes());
+
+        movie
+    }
+
+    pub fn decode(movie: &[u8]) -> Result<Movie, MovieError> {
+        if !movie.starts_with(MAGIC) {
+            return Err(MovieError::NotAMovie);
+        }
+        if movie.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(MovieError::Corrupt);
+        }
+        let (data, checksum) = movie.split_at(movie.len() - CHECKSUM_SIZE);
+        if crc32fast::hash(data).to_be_bytes() != checksum {
+            return Err(MovieError::Corrupt);
+        }
+
+        let field = |at: usize, len: usize| &data[at..at + len];
+        let number = |at: usize, len: usize| {
+            field(at, len)
+                .iter()
+                .fold(0u64, |number, &byte| number << 8 | byte as u64)
+        };
+
+        let version = number(4, 2) as u16;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = String::from_utf8(field(6, 40).to_vec()).map_err(|_| MovieError::Corrupt)?;
+        let count = number(63, 4) as usize;
+        if data.len() != HEADER_SIZE + count * 2 {
+            return Err(MovieError::Corrupt);
+        }
+        let frames = data[HEADER_SIZE..]
+            .chunks(2)
+            .map(|frame| u16::from_be_bytes([frame[0], frame[1]]))
+            .collect();
+
+        Ok(Movie {
+            rom_hash,
+            seed: number(46, 8),
+            ipf: number(54, 4) as u32,
+            quirks: quirks_from_bits(data[58]),
+            load_address: number(59, 2) as u16,
+            start_address: number(61, 2) as u16,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::rom;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::init(&rom::hash(&[0x12, 0x00]), 99, 15, Quirks::schip());
+        movie.load_address = 0x600;
+        movie.start_address = 0x602;
+        let mut keys = [false; 16];
+        movie.record(&keys);
+        keys[0xA] = true;
+        keys[0xF] = true;
+        movie.record(&keys);
+
+        movie
+    }
+
+    #[test]
+    fn test_keys() {
+        let movie = movie();
+
+        assert_eq!(movie.len(), 2);
+        assert_eq!(movie.keys(0), Some([false; 16]));
+        let keys = movie.keys(1).unwrap();
+        assert!(keys[0xA] && keys[0xF] && !keys[0]);
+        assert_eq!(movie.keys(2), None);
+    }
+
+    #[test]
+    fn test_encode_round_trips() {
+        let movie = movie();
+
+        assert_eq!(Movie::decode(&movie.encode()), Ok(movie));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut encoded = movie().encode();
+        assert_eq!(Movie::decode(b"C8ST"), Err(MovieError::NotAMovie));
+        assert_eq!(
+            Movie::decode(&encoded[..encoded.len() - 1]),
+            Err(MovieError::Corrupt)
+        );
+
+        encoded[HEADER_SIZE] ^= 1;
+        assert_eq!(Movie::decode(&encoded), Err(MovieError::Corrupt));
+    }
+
+    #[test]
+    fn test_replay_is_identical() {
+        // draws a random sprite where the held key says, forever
+        let rom = [
+            0xC0, 0x3F, // RND V0, 0x3f
+            0xC1, 0x1F, // RND V1, 0x1f
+            0x62, 0x00, // LD V2, 0
+            0xE2, 0x9E, // SKP V2
+            0x12, 0x0C, // JP 0x20c
+            0x00, 0xE0, // CLS
+            0xF1, 0x29, // LD F, V1
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x12, 0x00, // JP 0x200
+        ];
+        let run = |movie: &Movie| {
+            let mut cpu = Cpu::init(movie.quirks);
+            cpu.load(&rom).unwrap();
+            cpu.reseed(movie.seed);
+            for frame in 0..movie.len() {
+                cpu.keys = movie.keys(frame).unwrap();
+                cpu.run_frame(movie.ipf).unwrap();
+            }
+            cpu.save_state()
+        };
+
+        let mut movie = Movie::init(&rom::hash(&rom), 1234, 20, Quirks::default());
+        for frame in 0..120 {
+            let mut keys = [false; 16];
+            keys[0] = frame % 7 == 0;
+            movie.record(&keys);
+        }
+        let replayed = Movie::decode(&movie.encode()).unwrap();
+
+        assert_eq!(run(&movie), run(&replayed));
+    }
+}
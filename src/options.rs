@@ -0,0 +1,378 @@
+use crate::config::{DisplayConfig, Keymap};
+use chip8::beeper::{Tone, Waveform};
+use chip8::cpu::START_ADDRESS;
+use chip8::database::RomInfo;
+use chip8::Quirks;
+
+use std::slice::Iter;
+use std::str::FromStr;
+use std::time::Duration;
+
+const USAGE: &str = "usage: chip8 [options] <rom>
+
+the rom is read from stdin when given as -
+
+options:
+    --ipf <n>                instructions per 60 Hz frame (default 10)
+    --quirks <preset>        vip (default), chip48, schip or xochip
+    --[no-]vf-reset          OR, AND and XOR reset VF
+    --[no-]increment-i       LD [I], Vx and LD Vx, [I] advance I
+    --[no-]shift-vx          SHR and SHL shift Vx in place
+    --[no-]jump-vx           JP V0, addr adds Vx instead of V0
+    --[no-]clip-sprites      clip sprites at the screen edges
+    --waveform <shape>       square (default), triangle, sawtooth or sine
+    --frequency <hz>         pitch of the buzzer (default 440)
+    --volume <0-1>           volume of the buzzer (default 0.25)
+    --mute                   start muted, M toggles sound while running
+    --palette <name>         mono (default), green, amber or gameboy
+    --foreground <color>     color of lit pixels, as #rrggbb
+    --background <color>     color of unlit pixels
+    --colors <c0,c1,c2,c3>   all four XO-CHIP plane colors
+    --scale <n>              window pixels per CHIP-8 pixel, 1 to 100 (default 10)
+    --fit                    scale the screen to fill the window, not by whole pixels
+    --grid                   draw lines between pixels
+    --debug                  start paused with a debugger prompt on stdin
+    --rewind-frames <n>      frames of history Backspace rewinds through (default 600)
+    --rewind-memory <mib>    memory the rewind history may use (default 64)
+    --seed <n>               seed of RND (default random, 0 when headless)
+    --record <file>          record the keys held each frame to a movie
+    --play <file>            replay a recorded movie
+    --config <file>          config file (default ~/.config/chip8/config.toml)
+    --bind <key>=<host keys> bind a CHIP-8 key to comma separated host keys, e.g. 5=Up,W
+    --profile <file>         write an execution profile to file and its call stacks to file.folded
+    --no-database            don't take quirks, speed and key hints from the rom database
+    --database <file>        use a rom database file, e.g. the community programs.json
+    --load-address <addr>    address the rom is loaded at (default 0x200, 0x600 for ETI 660)
+    --start-address <addr>   address execution starts from (default the load address)
+    --low-memory             allow a load address below 0x200, over the font
+
+terminal options:
+    --tui                    play in the terminal instead of a window
+    --braille                draw with braille, 2x4 pixels per character
+    --key-timeout <ms>       release keys not repeated for this long (default 150)
+
+headless options:
+    --headless               run without a window and print a hash of the screen
+    --frames <n>             number of frames to run (default 600)
+    --until <addr>           stop before executing addr, failing if never reached
+    --keys <file>            key script of `<frame> <keys held>` lines
+    --screenshot <file>      write the final screen as .png or .pbm";
+
+const DEFAULT_IPF: u32 = 10;
+const DEFAULT_FRAMES: u32 = 600;
+const DEFAULT_REWIND_FRAMES: usize = 600;
+const DEFAULT_REWIND_MEMORY: usize = 64;
+const DEFAULT_KEY_TIMEOUT: u64 = 150;
+
+/// Command line options of the `chip8` binary.
+// the sound, debugger and terminal options only apply to their frontends
+#[cfg_attr(not(all(feature = "sdl", feature = "tui")), allow(dead_code))]
+pub struct Options {
+    pub rom: String,
+    pub quirks: Quirks,
+    pub ipf: u32,
+    // whether the quirks preset and ipf were given, so the rom database
+    // leaves them
+    quirks_given: bool,
+    ipf_given: bool,
+    // single quirks flipped on the command line, on top of any preset
+    toggles: Vec<(String, bool)>,
+    pub database: bool,
+    // database read instead of the bundled one
+    pub database_file: Option<String>,
+    pub tone: Tone,
+    pub mute: bool,
+    pub debug: bool,
+    pub rewind_frames: usize,
+    // in bytes
+    pub rewind_memory: usize,
+    pub headless: bool,
+    pub frames: u32,
+    pub until: Option<u16>,
+    pub load_address: u16,
+    pub start_address: u16,
+    // whether the rom may load over the font and interpreter
+    pub low_memory: bool,
+    pub keys: Option<String>,
+    pub screenshot: Option<String>,
+    pub seed: Option<u64>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub profile: Option<String>,
+    pub config: Option<String>,
+    // applied over the config file's keymap
+    pub bindings: Keymap,
+    // display settings by name, applied over the config file's
+    pub display: Vec<(String, String)>,
+    pub tui: bool,
+    pub braille: bool,
+    pub key_timeout: Duration,
+}
+
+impl Options {
+    /// Parses `args`, not including the program name.
+    ///
+    /// Individual quirk flags are applied on top of the `--quirks` preset
+    /// regardless of the order they are given in.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom = None;
+        let mut quirks = Quirks::vip();
+        let mut ipf = DEFAULT_IPF;
+        let mut tone = Tone::default();
+        let mut mute = false;
+        let mut debug = false;
+        let mut rewind_frames = DEFAULT_REWIND_FRAMES;
+        let mut rewind_memory = DEFAULT_REWIND_MEMORY;
+        let mut headless = false;
+        let mut frames = DEFAULT_FRAMES;
+        let mut until = None;
+        let mut load_address = START_ADDRESS;
+        let mut start_address = None;
+        let mut low_memory = false;
+        let mut keys = None;
+        let mut screenshot = None;
+        let mut seed = None;
+        let mut record = None;
+        let mut play = None;
+        let mut profile = None;
+        let mut config = None;
+        let mut bindings = Keymap::empty();
+        let mut display = Vec::new();
+        let mut tui = false;
+        let mut braille = false;
+        let mut key_timeout = DEFAULT_KEY_TIMEOUT;
+        let mut toggles = Vec::new();
+        let mut quirks_given = false;
+        let mut ipf_given = false;
+        let mut database = true;
+        let mut database_file = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ipf" => {
+                    ipf = value(&mut args, arg)?;
+                    ipf_given = true;
+                }
+                "--no-database" => database = false,
+                "--database" => database_file = Some(value(&mut args, arg)?),
+                "--waveform" => {
+                    let name: String = value(&mut args, arg)?;
+                    tone.waveform = Waveform::from_name(&name)
+                        .ok_or_else(|| usage(&format!("unknown waveform {}", name)))?;
+                }
+                "--frequency" => tone.frequency = value(&mut args, arg)?,
+                "--volume" => tone.volume = value::<f32>(&mut args, arg)?.clamp(0.0, 1.0),
+                "--mute" => mute = true,
+                "--debug" => debug = true,
+                "--rewind-frames" => rewind_frames = value(&mut args, arg)?,
+                "--rewind-memory" => rewind_memory = value(&mut args, arg)?,
+                "--headless" => headless = true,
+                "--frames" => frames = value(&mut args, arg)?,
+                "--until" => until = Some(address(&mut args, arg)?),
+                "--load-address" => load_address = address(&mut args, arg)?,
+                "--start-address" => start_address = Some(address(&mut args, arg)?),
+                "--low-memory" => low_memory = true,
+                "--keys" => keys = Some(value(&mut args, arg)?),
+                "--screenshot" => screenshot = Some(value(&mut args, arg)?),
+                "--seed" => seed = Some(value(&mut args, arg)?),
+                "--record" => record = Some(value(&mut args, arg)?),
+                "--play" => play = Some(value(&mut args, arg)?),
+                "--profile" => profile = Some(value(&mut args, arg)?),
+                "--palette" | "--foreground" | "--background" | "--colors" | "--scale" => {
+                    display.push((arg[2..].to_string(), value(&mut args, arg)?))
+                }
+                "--fit" | "--grid" => display.push((arg[2..].to_string(), "true".to_string())),
+                "--tui" => tui = true,
+                "--braille" => braille = true,
+                "--key-timeout" => key_timeout = value(&mut args, arg)?,
+                "--config" => config = Some(value(&mut args, arg)?),
+                "--bind" => {
+                    let binding: String = value(&mut args, arg)?;
+                    let (key, host_keys) =
+                        Keymap::parse_binding(&binding).map_err(|e| usage(&e))?;
+                    bindings.bind(key, host_keys);
+                }
+                "--quirks" => {
+                    quirks_given = true;
+                    let name = args
+                        .next()
+                        .ok_or_else(|| usage("--quirks needs a preset"))?;
+                    quirks = Quirks::preset(name)
+                        .ok_or_else(|| usage(&format!("unknown quirks preset {}", name)))?;
+                }
+                flag if flag.starts_with("--") => {
+                    let (name, on) = match flag.strip_prefix("--no-") {
+                        Some(name) => (name, false),
+                        None => (&flag[2..], true),
+                    };
+                    toggles.push((name.to_string(), on));
+                }
+                path => {
+                    if rom.replace(path.to_string()).is_some() {
+                        return Err(usage("only one rom can be given"));
+                    }
+                }
+            }
+        }
+
+        for (name, on) in &toggles {
+            if !toggle(&mut quirks, name, *on) {
+                return Err(usage(&format!("unknown option --{}", name)));
+            }
+        }
+
+        DisplayConfig::default()
+            .apply(&display)
+            .map_err(|e| usage(&e))?;
+        let rewind_memory = rewind_memory
+            .checked_mul(1 << 20)
+            .ok_or_else(|| usage("--rewind-memory is too large"))?;
+        if record.is_some() && play.is_some() {
+            return Err(usage("--record and --play can't be combined"));
+        }
+        if debug && (record.is_some() || play.is_some()) {
+            return Err(usage("--debug can't be combined with --record or --play"));
+        }
+        if debug && profile.is_some() {
+            return Err(usage("--debug can't be combined with --profile"));
+        }
+        if tui && (record.is_some() || play.is_some()) {
+            return Err(usage("--tui can't be combined with --record or --play"));
+        }
+
+        Ok(Options {
+            rom: rom.ok_or_else(|| usage("missing rom"))?,
+            quirks,
+            ipf,
+            quirks_given,
+            ipf_given,
+            toggles,
+            database,
+            database_file,
+            tone,
+            mute,
+            debug,
+            rewind_frames,
+            rewind_memory,
+            headless,
+            frames,
+            until,
+            load_address,
+            start_address: start_address.unwrap_or(load_address),
+            low_memory,
+            keys,
+            screenshot,
+            seed,
+            record,
+            play,
+            profile,
+            config,
+            bindings,
+            display,
+            tui,
+            braille,
+            key_timeout: Duration::from_millis(key_timeout),
+        })
+    }
+
+    /// Takes the quirks and speed the rom database recommends, except those
+    /// given on the command line. Single quirk flags still apply on top of
+    /// the database's quirks.
+    pub fn apply_rom_info(&mut self, info: &RomInfo) {
+        if let (false, Some(quirks)) = (self.quirks_given, info.quirks) {
+            self.quirks = quirks;
+            for (name, on) in &self.toggles {
+                toggle(&mut self.quirks, name, *on);
+            }
+        }
+        if let (false, Some(tickrate)) = (self.ipf_given, info.tickrate) {
+            self.ipf = tickrate;
+        }
+    }
+}
+
+/// Sets the quirk named as its flag, e.g. `jump-vx`, returning whether
+/// there is one.
+fn toggle(quirks: &mut Quirks, name: &str, on: bool) -> bool {
+    match name {
+        "vf-reset" => quirks.vf_reset = on,
+        "increment-i" => quirks.increment_i = on,
+        "shift-vx" => quirks.shift_vx = on,
+        "jump-vx" => quirks.jump_vx = on,
+        "clip-sprites" => quirks.clip_sprites = on,
+        _ => return false,
+    }
+
+    true
+}
+
+/// Parses the value following `flag`.
+fn value<T: FromStr>(args: &mut Iter<String>, flag: &str) -> Result<T, String> {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| usage(&format!("{} needs a valid value", flag)))
+}
+
+/// Parses the address following `flag`, in hex with a `0x` prefix or in
+/// decimal.
+fn address(args: &mut Iter<String>, flag: &str) -> Result<u16, String> {
+    let addr: String = value(args, flag)?;
+    let parsed = match addr.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => addr.parse(),
+    };
+
+    parsed.map_err(|_| usage(&format!("invalid address {}", addr)))
+}
+
+fn usage(error: &str) -> String {
+    format!("{}\n\n{}", error, USAGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    fn parse(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args).unwrap()
+    }
+
+    fn info(quirks: Quirks) -> RomInfo {
+        RomInfo {
+            title: "Game".to_string(),
+            authors: Vec::new(),
+            platform: "superchip".to_string(),
+            quirks: Some(quirks),
+            tickrate: Some(30),
+            keys: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_toggles_apply_over_database_quirks() {
+        let mut options = parse(&["--no-jump-vx", "game.ch8"]);
+        options.apply_rom_info(&info(Quirks::schip()));
+
+        assert_eq!(
+            options.quirks,
+            Quirks {
+                jump_vx: false,
+                ..Quirks::schip()
+            }
+        );
+        assert_eq!(options.ipf, 30);
+    }
+
+    #[test]
+    fn test_preset_overrides_database_quirks() {
+        let mut options = parse(&["--quirks", "xochip", "--ipf", "15", "game.ch8"]);
+        options.apply_rom_info(&info(Quirks::schip()));
+
+        assert_eq!(options.quirks, Quirks::xochip());
+        assert_eq!(options.ipf, 15);
+    }
+}
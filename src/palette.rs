@@ -0,0 +1,104 @@
+/// A color as red, green and blue.
+pub type Rgb = (u8, u8, u8);
+
+/// Colors of the four plane combinations: background, plane 1 (the
+/// foreground), plane 2 and both planes. Plain CHIP-8 only uses the first
+/// two; XO-CHIP draws with all four.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            colors: [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)],
+        }
+    }
+}
+
+impl Palette {
+    /// `mono` (white on black), `green` (phosphor), `amber` or `gameboy`.
+    pub fn preset(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "mono" => return Some(Palette::default()),
+            "green" => [
+                (0x0a, 0x1a, 0x0a),
+                (0x33, 0xff, 0x33),
+                (0x1f, 0x9f, 0x1f),
+                (0x14, 0x66, 0x14),
+            ],
+            "amber" => [
+                (0x1a, 0x10, 0x00),
+                (0xff, 0xb0, 0x00),
+                (0xa6, 0x73, 0x00),
+                (0x66, 0x47, 0x00),
+            ],
+            // the DMG's four greens, darkest on lightest
+            "gameboy" => [
+                (0x9b, 0xbc, 0x0f),
+                (0x0f, 0x38, 0x0f),
+                (0x30, 0x62, 0x30),
+                (0x8b, 0xac, 0x0f),
+            ],
+            _ => return None,
+        };
+
+        Some(Palette { colors })
+    }
+
+    /// Parses `#rrggbb` or `#rgb`, with or without the `#`.
+    pub fn parse_color(text: &str) -> Result<Rgb, String> {
+        let hex = text.trim().trim_start_matches('#');
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|digit| digit.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("invalid color {}", text))?;
+
+        match *digits.as_slice() {
+            [r, g, b] => Ok((r * 17, g * 17, b * 17)),
+            [r1, r2, g1, g2, b1, b2] => Ok((r1 << 4 | r2, g1 << 4 | g2, b1 << 4 | b2)),
+            _ => Err(format!("invalid color {}, expected #rrggbb", text)),
+        }
+    }
+
+    /// A quarter of the way from the background to the foreground, for
+    /// lines that show without standing out.
+    pub fn grid(&self) -> Rgb {
+        let [(r0, g0, b0), (r1, g1, b1), ..] = self.colors;
+        let mix = |from: u8, to: u8| ((from as i32 * 3 + to as i32) / 4) as u8;
+
+        (mix(r0, r1), mix(g0, g1), mix(b0, b1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Palette::preset("mono"), Some(Palette::default()));
+        assert_eq!(
+            Palette::preset("amber").unwrap().colors[1],
+            (0xff, 0xb0, 0x00)
+        );
+        assert!(Palette::preset("gameboy").is_some() && Palette::preset("green").is_some());
+        assert_eq!(Palette::preset("sepia"), None);
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Palette::parse_color("#ffb000"), Ok((0xff, 0xb0, 0x00)));
+        assert_eq!(Palette::parse_color("0F380F"), Ok((0x0f, 0x38, 0x0f)));
+        assert_eq!(Palette::parse_color("#f80"), Ok((0xff, 0x88, 0x00)));
+        assert!(Palette::parse_color("#ff00").is_err());
+        assert!(Palette::parse_color("orange").is_err());
+    }
+
+    #[test]
+    fn test_grid() {
+        assert_eq!(Palette::default().grid(), (63, 63, 63));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Cycles spent in a subroutine, by the address it is called at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Routine {
+    pub calls: u64,
+    /// Instructions run in the routine and everything it calls.
+    pub inclusive: u64,
+    /// Instructions run in the routine itself.
+    pub exclusive: u64,
+}
+
+/// Counts what a program spends its instructions on.
+///
+/// Frontends call [`Profiler::run_frame`] in place of [`Cpu::run_frame`], then
+/// write out [`Profiler::write_report`] and [`Profiler::write_folded`] when
+/// the program ends. Each instruction is counted by its address, its kind
+/// and the subroutines on the stack when it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profiler {
+    cycles: u64,
+    // by address
+    executed: Vec<u64>,
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    // by instruction name, e.g. DRW
+    instructions: HashMap<String, u64>,
+    routines: BTreeMap<u16, Routine>,
+    // by routines on the stack, outermost first
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::init()
+    }
+}
+
+/// The name of an instruction's variant, e.g. `LD_BYTE` for `LD_BYTE(0, 1)`.
+fn kind(inst: &Instruction) -> String {
+    // Debug prints the variant and then its operands
+    let name = format!("{:?}", inst);
+    match name.split_once('(') {
+        Some((kind, _)) => kind.to_string(),
+        None => name,
+    }
+}
+
+fn count(counts: &mut [u64], range: Option<Range<usize>>) {
+    for addr in range.unwrap_or(0..0) {
+        counts[addr] += 1;
+    }
+}
+
+fn routine_name(addr: u16) -> String {
+    format!("sub_{:03x}", addr)
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    part as f64 * 100.0 / whole.max(1) as f64
+}
+
+impl Profiler {
+    pub fn init() -> Profiler {
+        Profiler {
+            cycles: 0,
+            executed: vec![0; MEMORY_SIZE],
+            reads: vec![0; MEMORY_SIZE],
+            writes: vec![0; MEMORY_SIZE],
+            instructions: HashMap::new(),
+            routines: BTreeMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Instructions run so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Times the instruction at `addr` ran.
+    pub fn executed(&self, addr: u16) -> u64 {
+        self.executed[addr as usize]
+    }
+
+    /// Times the byte at `addr` was read and written as data.
+    pub fn accesses(&self, addr: u16) -> (u64, u64) {
+        (self.reads[addr as usize], self.writes[addr as usize])
+    }
+
+    /// Times instructions of a kind, e.g. `DRW`, ran.
+    pub fn instruction(&self, kind: &str) -> u64 {
+        self.instructions.get(kind).copied().unwrap_or(0)
+    }
+
+    /// The subroutine called at `addr`, if it ever ran.
+    pub fn routine(&self, addr: u16) -> Option<Routine> {
+        self.routines.get(&addr).copied()
+    }
+
+    /// The subroutines on `cpu`'s stack, outermost first. The stack only
+    /// holds return addresses, so each routine is the target of the `CALL`
+    /// just before one.
+    fn frames(cpu: &Cpu) -> Vec<u16> {
+        cpu.stack()
+            .iter()
+            .map(|&ret| {
+                let call = ret.wrapping_sub(2) as usize;
+                let word = cpu
+                    .mem()
+                    .get(call..call + 2)
+                    .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16);
+                match word.map(Instruction::parse) {
+                    Some(Ok(Instruction::CALL_ADDR(addr))) => addr,
+                    // the call was overwritten since, so name it by its site
+                    _ => call as u16,
+                }
+            })
+            .collect()
+    }
+
+    /// Runs and counts one instruction, like [`Cpu::cycle`]. Nothing is
+    /// counted when it fails.
+    pub fn cycle(&mut self, cpu: &mut Cpu) -> Result<(), Chip8Error> {
+        if cpu.exited() {
+            return Ok(());
+        }
+        let pc = cpu.pc();
+        let inst = cpu.peek()?;
+        let frames = Profiler::frames(cpu);
+        cpu.cycle()?;
+
+        self.cycles += 1;
+        self.executed[pc as usize] += 1;
+        *self.instructions.entry(kind(&inst)).or_insert(0) += 1;
+        count(&mut self.reads, cpu.last_read());
+        count(&mut self.writes, cpu.last_write());
+
+        if let Instruction::CALL_ADDR(addr) = inst {
+            // a call that overflows the stack fails above
+            self.routines.entry(addr).or_default().calls += 1;
+        }
+        if let Some(&innermost) = frames.last() {
+            self.routines.entry(innermost).or_default().exclusive += 1;
+        }
+        // a recursive routine still only runs the instruction once
+        let mut seen = frames.clone();
+        seen.sort_unstable();
+        seen.dedup();
+        for addr in seen {
+            self.routines.entry(addr).or_default().inclusive += 1;
+        }
+        *self.stacks.entry(frames).or_insert(0) += 1;
+
+        Ok(())
+    }
+
+    /// Runs one 60 Hz frame like [`Cpu::run_frame`], counting every
+    /// instruction.
+    pub fn run_frame(&mut self, cpu: &mut Cpu, ipf: u32) -> Result<(), Chip8Error> {
+        for _ in 0..ipf {
+            if cpu.exited() {
+                break;
+            }
+            self.cycle(cpu)?;
+        }
+        cpu.tick_timers();
+
+        Ok(())
+    }
+
+    /// Writes the counts as text: instruction kinds and subroutines from the
+    /// busiest down, then every address that ran with the instruction now in
+    /// `cpu`'s memory there, then every byte read or written as data.
+    pub fn write_report<W: Write>(&self, cpu: &Cpu, mut out: W) -> io::Result<()> {
+        writeln!(out, "{} instructions", self.cycles)?;
+
+        writeln!(out, "\ninstructions")?;
+        let mut kinds: Vec<(&str, u64)> = self
+            .instructions
+            .iter()
+            .map(|(kind, &count)| (kind.as_str(), count))
+            .collect();
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (kind, count) in kinds {
+            let share = percent(count, self.cycles);
+            writeln!(out, "  {:<12}{:>12}{:>8.2}%", kind, count, share)?;
+        }
+
+        writeln!(out, "\nsubroutines   calls   inclusive   exclusive")?;
+        let mut routines: Vec<(&u16, &Routine)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (&addr, routine) in routines {
+            writeln!(
+                out,
+                "  {:<10}{:>8}{:>12}{:>12}  {:.2}% / {:.2}%",
+                routine_name(addr),
+                routine.calls,
+                routine.inclusive,
+                routine.exclusive,
+                percent(routine.inclusive, self.cycles),
+                percent(routine.exclusive, self.cycles)
+            )?;
+        }
+
+        writeln!(out, "\nexecuted")?;
+        for (addr, &count) in self.executed.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let inst = cpu
+                .mem()
+                .get(addr..addr + 2)
+                .map(|bytes| Instruction::parse((bytes[0] as u16) << 8 | bytes[1] as u16));
+            let text = match inst {
+                Some(Ok(inst)) => inst.to_string(),
+                _ => "?".to_string(),
+            };
+            writeln!(out, "  {:#06x}{:>12}  {}", addr, count, text)?;
+        }
+
+        writeln!(out, "\nmemory         reads      writes")?;
+        for (addr, (&reads, &writes)) in self.reads.iter().zip(&self.writes).enumerate() {
+            if reads > 0 || writes > 0 {
+                writeln!(out, "  {:#06x}{:>12}{:>12}", addr, reads, writes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the instructions run under each call stack in the folded
+    /// format flame graph tools read, e.g. `start;sub_2a4;sub_300 57`.
+    pub fn write_folded<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(frames, &count)| {
+                let names: Vec<String> = frames.iter().map(|&addr| routine_name(addr)).collect();
+                let mut stack = "start".to_string();
+                for name in names {
+                    stack += ";";
+                    stack += &name;
+                }
+                (stack, count)
+            })
+            .collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // calls 0x20a twice, which calls 0x210 once, then spins on 0x208
+    const PROGRAM: [u8; 22] = [
+        0x22, 0x0A, // 200: CALL 0x20a
+        0x22, 0x0A, // 202: CALL 0x20a
+        0xA3, 0x00, // 204: LD I, 0x300
+        0xF1, 0x65, // 206: LD V1, [I]
+        0x12, 0x08, // 208: JP 0x208
+        0x60, 0x01, // 20a: LD V0, 1
+        0x22, 0x10, // 20c: CALL 0x210
+        0x00, 0xEE, // 20e: RET
+        0x61, 0x02, // 210: LD V1, 2
+        0x00, 0xEE, // 212: RET
+        0x00, 0x00,
+    ];
+
+    fn profile(cycles: usize) -> (Cpu, Profiler) {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&PROGRAM).unwrap();
+        let mut profiler = Profiler::init();
+        for _ in 0..cycles {
+            profiler.cycle(&mut cpu).unwrap();
+        }
+
+        (cpu, profiler)
+    }
+
+    #[test]
+    fn test_counts() {
+        let (_, profiler) = profile(16);
+
+        assert_eq!(profiler.cycles(), 16);
+        assert_eq!(profiler.executed(0x20a), 2);
+        assert_eq!(profiler.executed(0x208), 2);
+        assert_eq!(profiler.instruction("CALL_ADDR"), 4);
+        assert_eq!(profiler.instruction("RET"), 4);
+        assert_eq!(profiler.accesses(0x300), (1, 0));
+        assert_eq!(profiler.accesses(0x302), (0, 0));
+    }
+
+    #[test]
+    fn test_routines() {
+        let (_, profiler) = profile(16);
+
+        // LD, CALL and RET, twice, plus the callee
+        assert_eq!(
+            profiler.routine(0x20a),
+            Some(Routine {
+                calls: 2,
+                inclusive: 10,
+                exclusive: 6,
+            })
+        );
+        assert_eq!(
+            profiler.routine(0x210),
+            Some(Routine {
+                calls: 2,
+                inclusive: 4,
+                exclusive: 4,
+            })
+        );
+        assert_eq!(profiler.routine(0x200), None);
+    }
+
+    #[test]
+    fn test_folded() {
+        let (_, profiler) = profile(16);
+        let mut out = Vec::new();
+        profiler.write_folded(&mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "start 6\nstart;sub_20a 6\nstart;sub_20a;sub_210 4\n"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let (cpu, profiler) = profile(16);
+        let mut out = Vec::new();
+        profiler.write_report(&cpu, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+
+        assert!(report.starts_with("16 instructions\n"));
+        assert!(report.contains("  sub_20a          2          10           6"));
+        assert!(report.contains("  0x020a           2  LD V0, 0x01\n"));
+        assert!(report.contains("  0x0300           1           0\n"));
+    }
+
+    #[test]
+    fn test_failed_cycle_not_counted() {
+        let mut cpu = Cpu::init(Quirks::default());
+        cpu.load(&[0x00, 0xEE]).unwrap();
+        let mut profiler = Profiler::init();
+
+        assert!(profiler.cycle(&mut cpu).is_err());
+        assert_eq!(profiler.cycles(), 0);
+    }
+}
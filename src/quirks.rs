@@ -0,0 +1,95 @@
+/// Behaviour of opcodes that differ between CHIP-8 implementations.
+///
+/// The original COSMAC VIP interpreter, CHIP-48 on the HP-48 calculators,
+/// SUPER-CHIP and XO-CHIP disagree on a handful of instructions, and ROMs are
+/// usually written against one of them. Each flag picks one interpretation;
+/// the presets bundle the flags of a known platform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// `OR`, `AND` and `XOR` reset VF to 0.
+    pub vf_reset: bool,
+    /// `LD [I], Vx` and `LD Vx, [I]` leave I pointing past the last register
+    /// instead of leaving it unchanged.
+    pub increment_i: bool,
+    /// `SHR` and `SHL` shift Vx in place instead of storing the shifted Vy
+    /// in Vx.
+    pub shift_vx: bool,
+    /// `JP V0, addr` jumps to `addr + Vx`, where x is the high nibble of
+    /// `addr`, instead of `addr + V0`.
+    pub jump_vx: bool,
+    /// `DRW` clips sprites at the screen edges instead of wrapping them
+    /// around to the opposite edge.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub fn vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            increment_i: true,
+            shift_vx: false,
+            jump_vx: false,
+            clip_sprites: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: true,
+            shift_vx: true,
+            jump_vx: true,
+            clip_sprites: true,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub fn schip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: false,
+            shift_vx: true,
+            jump_vx: true,
+            clip_sprites: true,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: true,
+            shift_vx: false,
+            jump_vx: false,
+            clip_sprites: false,
+        }
+    }
+
+    /// Looks up a preset by name: `vip`, `chip48`, `schip` or `xochip`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "chip8" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// Defaults to how this emulator behaved before quirks were configurable:
+    /// shifts act on Vx in place and sprites clip, but nothing else. The
+    /// command line defaults to [`Quirks::vip`] instead.
+    fn default() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: false,
+            shift_vx: true,
+            jump_vx: false,
+            clip_sprites: true,
+        }
+    }
+}
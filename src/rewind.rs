@@ -0,0 +1,359 @@
+use crate::cpu::Cpu;
+use crate::state::StateError;
+
+use std::collections::VecDeque;
+
+/// Snapshots stored as a diff between keyframes.
+const KEYFRAME_INTERVAL: usize = 60;
+/// Unchanged bytes between two changes that are still copied into the same
+/// run, since every run costs its offset and length.
+const RUN_GAP: usize = 8;
+/// Approximate bookkeeping cost of a run or snapshot beyond its bytes.
+const OVERHEAD: usize = 32;
+
+/// Changed runs of bytes against a keyframe, as offset and new bytes.
+type Delta = Vec<(usize, Vec<u8>)>;
+
+/// A full save state followed by the snapshots taken after it, stored as
+/// deltas against it.
+struct Segment {
+    keyframe: Vec<u8>,
+    deltas: Vec<Delta>,
+    // approximate memory used
+    size: usize,
+}
+
+impl Segment {
+    fn frames(&self) -> usize {
+        1 + self.deltas.len()
+    }
+
+    /// The latest snapshot in the segment.
+    fn latest(&self) -> Vec<u8> {
+        let mut state = self.keyframe.clone();
+        if let Some(delta) = self.deltas.last() {
+            for (offset, bytes) in delta {
+                state[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+            }
+        }
+
+        state
+    }
+}
+
+fn delta_size(delta: &Delta) -> usize {
+    delta
+        .iter()
+        .map(|(_, bytes)| bytes.len() + OVERHEAD)
+        .sum::<usize>()
+        + OVERHEAD
+}
+
+/// Runs of bytes in `state` that differ from `keyframe`, which must be the
+/// same length.
+fn diff(keyframe: &[u8], state: &[u8]) -> Delta {
+    let mut delta: Delta = Vec::new();
+    // most of a state is unchanged memory, so skip equal blocks wholesale
+    let changed = (0..state.len())
+        .step_by(64)
+        .filter(|&block| {
+            let end = (block + 64).min(state.len());
+            keyframe[block..end] != state[block..end]
+        })
+        .flat_map(|block| block..(block + 64).min(state.len()))
+        .filter(|&at| keyframe[at] != state[at]);
+
+    let mut run: Option<(usize, usize)> = None;
+    for at in changed {
+        run = match run {
+            Some((start, end)) if at <= end + RUN_GAP => Some((start, at + 1)),
+            Some((start, end)) => {
+                delta.push((start, state[start..end].to_vec()));
+                Some((at, at + 1))
+            }
+            None => Some((at, at + 1)),
+        };
+    }
+    if let Some((start, end)) = run {
+        delta.push((start, state[start..end].to_vec()));
+    }
+
+    delta
+}
+
+/// A ring buffer of machine snapshots for stepping backwards in time.
+///
+/// Call [`Rewind::record`] before each step, a frame or an instruction, and
+/// [`Rewind::undo`] to return to the state before the latest one. Snapshots
+/// are save states; only one every `KEYFRAME_INTERVAL` is kept whole and the
+/// rest are stored as the bytes that changed since. The oldest snapshots are
+/// dropped, a keyframe and its deltas at a time, to stay within both
+/// `capacity` snapshots and `budget` bytes. Keyframes are taken more often
+/// when a whole segment wouldn't fit, and a budget too small for even one
+/// keyframe keeps nothing.
+pub struct Rewind {
+    segments: VecDeque<Segment>,
+    capacity: usize,
+    budget: usize,
+    frames: usize,
+    size: usize,
+}
+
+impl Rewind {
+    pub fn init(capacity: usize, budget: usize) -> Rewind {
+        Rewind {
+            segments: VecDeque::new(),
+            capacity,
+            budget,
+            frames: 0,
+            size: 0,
+        }
+    }
+
+    /// Number of snapshots held.
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// Approximate memory held by the snapshots, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.frames = 0;
+        self.size = 0;
+    }
+
+    /// Takes a snapshot of `cpu`.
+    pub fn record(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+        let state = cpu.save_state();
+
+        // the segment being recorded into can't be dropped until the next
+        // keyframe, so it must fit in what is kept on its own
+        let interval = KEYFRAME_INTERVAL.min(self.capacity);
+        let delta = match self.segments.back() {
+            Some(segment)
+                if segment.frames() < interval
+                    && segment.size <= self.budget / 2
+                    && segment.keyframe.len() == state.len() =>
+            {
+                Some(diff(&segment.keyframe, &state))
+            }
+            _ => None,
+        };
+        let grown = match (delta, self.segments.back_mut()) {
+            (Some(delta), Some(segment)) => {
+                let size = delta_size(&delta);
+                segment.deltas.push(delta);
+                segment.size += size;
+                size
+            }
+            _ => {
+                let size = state.len() + OVERHEAD;
+                self.segments.push_back(Segment {
+                    keyframe: state,
+                    deltas: Vec::new(),
+                    size,
+                });
+                size
+            }
+        };
+        self.frames += 1;
+        self.size += grown;
+
+        while self.frames > self.capacity || self.size > self.budget {
+            match self.segments.pop_front() {
+                Some(oldest) => {
+                    self.frames -= oldest.frames();
+                    self.size -= oldest.size;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the latest snapshot into `cpu` and drops it, returning
+    /// whether there was one. A snapshot that fails to load, such as one
+    /// taken with another ROM loaded, is dropped all the same.
+    pub fn undo(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        let state = match self.segments.back() {
+            Some(segment) => segment.latest(),
+            None => return Ok(false),
+        };
+        self.discard();
+        cpu.load_state(&state)?;
+
+        Ok(true)
+    }
+
+    /// Drops the latest snapshot without restoring it.
+    pub fn discard(&mut self) {
+        let segment = match self.segments.back_mut() {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        match segment.deltas.pop() {
+            Some(delta) => {
+                let size = delta_size(&delta);
+                segment.size -= size;
+                self.size -= size;
+            }
+            None => {
+                self.size -= segment.size;
+                self.segments.pop_back();
+            }
+        }
+        self.frames -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+
+    fn counter() -> Cpu {
+        let mut cpu = Cpu::init(Quirks {
+            increment_i: false,
+            ..Quirks::default()
+        });
+        // ADD V0, 1; LD [I], V0; JP 0x200
+        cpu.load(&[0x70, 0x01, 0xF0, 0x55, 0x12, 0x00]).unwrap();
+        cpu.execute(Instruction::LD_I(0x300)).unwrap();
+
+        cpu
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::init(1000, usize::MAX);
+        for _ in 0..150 {
+            rewind.record(&cpu);
+            cpu.run_frame(3).unwrap();
+        }
+        assert_eq!(cpu.v()[0], 150);
+
+        assert!(rewind.undo(&mut cpu).unwrap());
+        assert_eq!(cpu.v()[0], 149);
+        for _ in 0..100 {
+            rewind.undo(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.v()[0], 49);
+        assert_eq!(cpu.mem()[0x300], 49);
+        assert_eq!(rewind.len(), 49);
+    }
+
+    #[test]
+    fn test_undo_to_the_start() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::init(1000, usize::MAX);
+        rewind.record(&cpu);
+        cpu.run_frame(3).unwrap();
+
+        assert!(rewind.undo(&mut cpu).unwrap());
+        assert_eq!(cpu.v()[0], 0);
+        assert!(!rewind.undo(&mut cpu).unwrap());
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::init(100, usize::MAX);
+        for _ in 0..1000 {
+            rewind.record(&cpu);
+            cpu.run_frame(3).unwrap();
+        }
+
+        assert!(rewind.len() <= 100);
+        assert!(rewind.len() > 100 - KEYFRAME_INTERVAL);
+    }
+
+    #[test]
+    fn test_capacity_below_keyframe_interval() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::init(10, usize::MAX);
+        for _ in 0..100 {
+            rewind.record(&cpu);
+            cpu.run_frame(3).unwrap();
+            assert!(rewind.len() <= 10);
+        }
+
+        assert!(rewind.undo(&mut cpu).unwrap());
+        assert_eq!(cpu.v()[0], 99);
+    }
+
+    #[test]
+    fn test_budget_below_segment() {
+        let mut cpu = counter();
+        let state = cpu.save_state().len();
+        let mut rewind = Rewind::init(1000, state + state / 2);
+        for _ in 0..100 {
+            rewind.record(&cpu);
+            cpu.run_frame(3).unwrap();
+            assert!(rewind.size() <= state + state / 2);
+        }
+        assert!(!rewind.is_empty());
+
+        // too small for a single keyframe
+        let mut rewind = Rewind::init(1000, state / 2);
+        rewind.record(&cpu);
+        assert!(rewind.is_empty());
+        assert!(!rewind.undo(&mut cpu).unwrap());
+    }
+
+    #[test]
+    fn test_discard() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::init(1000, usize::MAX);
+        rewind.record(&cpu);
+        cpu.run_frame(3).unwrap();
+        rewind.record(&cpu);
+        rewind.discard();
+
+        assert!(rewind.undo(&mut cpu).unwrap());
+        assert_eq!(cpu.v()[0], 0);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cpu = counter();
+        let state = cpu.save_state().len();
+        let mut rewind = Rewind::init(1000, state * 3);
+        for _ in 0..1000 {
+            rewind.record(&cpu);
+            cpu.run_frame(3).unwrap();
+        }
+
+        assert!(rewind.size() <= state * 3);
+        // deltas are far smaller than keyframes
+        assert!(rewind.len() > KEYFRAME_INTERVAL);
+    }
+
+    #[test]
+    fn test_diff() {
+        let keyframe = [0u8; 32];
+        let mut state = keyframe;
+        state[1] = 1;
+        state[4] = 1;
+        state[30] = 1;
+
+        assert_eq!(
+            diff(&keyframe, &state),
+            vec![(1, vec![1, 0, 0, 1]), (30, vec![1])]
+        );
+    }
+}
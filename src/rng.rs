@@ -0,0 +1,66 @@
+/// The SplitMix64 generator, which `RND` draws from.
+///
+/// Its whole state is a single `u64`, so a run can be reproduced from its
+/// seed and the generator saved and restored along with the rest of the
+/// machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn init(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// The current state, which [`Rng::init`] resumes from.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::init(42);
+        let mut b = Rng::init(42);
+        let mut c = Rng::init(43);
+        let a: Vec<u8> = (0..32).map(|_| a.next_u8()).collect();
+        let b: Vec<u8> = (0..32).map(|_| b.next_u8()).collect();
+        let c: Vec<u8> = (0..32).map(|_| c.next_u8()).collect();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_known_output() {
+        // first output of SplitMix64 seeded with 0
+        assert_eq!(Rng::init(0).next_u64(), 0xE220_A839_7B1D_CDAF);
+    }
+
+    #[test]
+    fn test_resume_from_state() {
+        let mut rng = Rng::init(7);
+        rng.next_u64();
+        let mut resumed = Rng::init(rng.state());
+
+        assert_eq!(rng.next_u64(), resumed.next_u64());
+    }
+}
@@ -1,19 +1,207 @@
-pub mod rom {
-    use crate::cpu::START_ADDRESS;
-    use std::ptr;
-
-    pub fn load_rom(filename: &String, mem: &mut [u8; 4096]) {
-        println!("Loading rom: {}", filename);
-        match std::fs::read(filename) {
-            Ok(bytes) => unsafe {
-                for (idx, element) in bytes.into_iter().enumerate() {
-                    ptr::write(&mut mem[idx + (START_ADDRESS as usize)], element);
-                }
-            },
-            Err(e) => {
-                panic!("{}", e);
+use crate::cpu::{MEMORY_SIZE, START_ADDRESS};
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+/// Errors raised while reading or loading a ROM.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomError {
+    /// The ROM could not be read from `path`.
+    Io { path: String, message: String },
+    /// `size` bytes do not fit in the `max` bytes of memory past the load
+    /// address.
+    TooLarge { size: usize, max: usize },
+    /// The load address is below [`START_ADDRESS`], over the font and the
+    /// interpreter's memory, without [`Rom::low_memory`].
+    LowLoadAddress(u16),
+    /// The ROM is shorter than a single instruction, e.g. an empty file.
+    Empty,
+    /// The start address leaves no whole instruction of the ROM to run.
+    StartOutsideRom(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io { path, message } => write!(f, "{}: {}", path, message),
+            RomError::TooLarge { size, max } => write!(
+                f,
+                "rom is {} bytes but only {} fit in memory past the load address",
+                size, max
+            ),
+            RomError::LowLoadAddress(addr) => write!(
+                f,
+                "load address {:#05x} is below {:#05x}, over the font and interpreter",
+                addr, START_ADDRESS
+            ),
+            RomError::Empty => write!(f, "rom is too short to hold an instruction"),
+            RomError::StartOutsideRom(addr) => {
+                write!(f, "start address {:#05x} is outside the rom", addr)
             }
         }
-        println!("Loaded rom: {}", filename);
+    }
+}
+
+impl Error for RomError {}
+
+/// A program and where it goes in memory.
+///
+/// Most programs load and start at [`START_ADDRESS`]; ETI 660 programs use
+/// 0x600 for both.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rom {
+    bytes: Vec<u8>,
+    hash: String,
+    /// Address the first byte is copied to.
+    pub load_address: u16,
+    /// Address execution starts from.
+    pub start_address: u16,
+    /// Allows a load address below [`START_ADDRESS`], for programs that
+    /// replace the font or patch the interpreter's memory.
+    pub low_memory: bool,
+}
+
+impl Rom {
+    /// Wraps `bytes` to load and start at [`START_ADDRESS`].
+    pub fn init(bytes: Vec<u8>) -> Rom {
+        Rom {
+            hash: hash(&bytes),
+            bytes,
+            load_address: START_ADDRESS,
+            start_address: START_ADDRESS,
+            low_memory: false,
+        }
+    }
+
+    /// Reads the ROM at `path`, or standard input for `-`.
+    pub fn read(path: &str) -> Result<Rom, RomError> {
+        let bytes = if path == "-" {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        } else {
+            std::fs::read(path)
+        };
+
+        bytes.map(Rom::init).map_err(|e| RomError::Io {
+            path: path.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Hex SHA-1 of the ROM's bytes.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Fails unless the ROM fits in memory from its load address on, that
+    /// address is past the interpreter's memory, the ROM holds at least one
+    /// instruction and the start address holds one of them.
+    pub fn check(&self) -> Result<(), RomError> {
+        if self.load_address < START_ADDRESS && !self.low_memory {
+            return Err(RomError::LowLoadAddress(self.load_address));
+        }
+        let max = MEMORY_SIZE - self.load_address as usize;
+        if self.bytes.len() > max {
+            return Err(RomError::TooLarge {
+                size: self.bytes.len(),
+                max,
+            });
+        }
+        if self.bytes.len() < 2 {
+            return Err(RomError::Empty);
+        }
+        let start = self.start_address as usize;
+        let end = self.load_address as usize + self.bytes.len();
+        if start < self.load_address as usize || start + 2 > end {
+            return Err(RomError::StartOutsideRom(self.start_address));
+        }
+
+        Ok(())
+    }
+}
+
+/// Hex SHA-1 of a ROM's bytes, which identifies it in save states.
+pub fn hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init() {
+        let rom = Rom::init(vec![0x00, 0xE0]);
+        assert_eq!(rom.bytes(), &[0x00, 0xE0]);
+        assert_eq!(rom.hash(), hash(&[0x00, 0xE0]));
+        assert_eq!((rom.load_address, rom.start_address), (0x200, 0x200));
+    }
+
+    #[test]
+    fn test_check_size() {
+        let mut rom = Rom::init(vec![0; MEMORY_SIZE - 0x600]);
+        assert_eq!(rom.check(), Ok(()));
+        rom.load_address = 0x600;
+        rom.start_address = 0x600;
+        assert_eq!(rom.check(), Ok(()));
+        rom.load_address = 0x601;
+        assert_eq!(
+            rom.check(),
+            Err(RomError::TooLarge {
+                size: MEMORY_SIZE - 0x600,
+                max: MEMORY_SIZE - 0x601,
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_load_address() {
+        let mut rom = Rom::init(vec![0x00, 0xE0]);
+        rom.load_address = 0x100;
+        rom.start_address = 0x100;
+        assert_eq!(rom.check(), Err(RomError::LowLoadAddress(0x100)));
+
+        rom.low_memory = true;
+        assert_eq!(rom.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_check_start_address() {
+        let mut rom = Rom::init(vec![0x00, 0xE0, 0x12, 0x02]);
+        rom.load_address = 0x600;
+        rom.start_address = 0x602;
+        assert_eq!(rom.check(), Ok(()));
+
+        rom.start_address = 0x603;
+        assert_eq!(rom.check(), Err(RomError::StartOutsideRom(0x603)));
+        rom.start_address = 0x5FE;
+        assert_eq!(rom.check(), Err(RomError::StartOutsideRom(0x5FE)));
+
+        // the last instruction fits right up to the end of memory
+        let mut rom = Rom::init(vec![0x12, 0x00]);
+        rom.load_address = (MEMORY_SIZE - 2) as u16;
+        rom.start_address = rom.load_address;
+        assert_eq!(rom.check(), Ok(()));
+        rom.start_address = (MEMORY_SIZE - 1) as u16;
+        assert_eq!(rom.check(), Err(RomError::StartOutsideRom(0xFFFF)));
+    }
+
+    #[test]
+    fn test_check_empty() {
+        assert_eq!(Rom::init(Vec::new()).check(), Err(RomError::Empty));
+        assert_eq!(Rom::init(vec![0x12]).check(), Err(RomError::Empty));
+    }
+
+    #[test]
+    fn test_read_missing() {
+        match Rom::read("roms/missing.ch8") {
+            Err(RomError::Io { path, .. }) => assert_eq!(path, "roms/missing.ch8"),
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::fmt;
+
+/// Identifies a save state file.
+pub const MAGIC: &[u8; 4] = b"C8ST";
+/// Version of the save state format written by [`crate::Cpu::save_state`].
+///
+/// Version 2 added the random number generator's state.
+pub const VERSION: u16 = 2;
+
+// magic, version, hex SHA-1 of the rom and payload length
+const HEADER_SIZE: usize = 4 + 2 + 40 + 4;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Errors raised while loading a save state. A state that fails to load
+/// leaves the machine untouched.
+#[derive(Debug, PartialEq, Clone)]
+pub enum StateError {
+    /// The data does not start with [`MAGIC`].
+    NotAState,
+    /// A format version this build cannot read.
+    UnsupportedVersion(u16),
+    /// The state was saved while running a different ROM.
+    WrongRom { expected: String, found: String },
+    /// The checksum does not match; the data is damaged.
+    Checksum,
+    /// The data ends early or holds impossible values.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::WrongRom { expected, found } => write!(
+                f,
+                "save state is for rom {}, not the loaded rom {}",
+                found, expected
+            ),
+            StateError::Checksum => write!(f, "save state checksum mismatch"),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Builds the payload of a save state.
+#[derive(Default)]
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Wraps the payload in a header naming the ROM it belongs to, followed
+    /// by a CRC-32 of everything before it.
+    pub fn seal(self, rom_hash: &str) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_SIZE + self.bytes.len() + CHECKSUM_SIZE);
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_be_bytes());
+        state.extend_from_slice(rom_hash.as_bytes());
+        state.extend_from_slice(&(self.bytes.len() as u32).to_be_bytes());
+        state.extend_from_slice(&self.bytes);
+
+        let checksum = crc32fast::hash(&state);
+        state.extend_from_slice(&checksum.to_be_bytes());
+
+        state
+    }
+}
+
+/// Reads the payload of a save state, failing with [`StateError::Corrupt`]
+/// past its end.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the header and checksum of `state`, returning its format
+    /// version and a reader over the payload.
+    pub fn open(state: &'a [u8], rom_hash: &str) -> Result<(u16, Reader<'a>), StateError> {
+        if !state.starts_with(MAGIC) {
+            return Err(StateError::NotAState);
+        }
+        if state.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(StateError::Corrupt);
+        }
+
+        let (data, checksum) = state.split_at(state.len() - CHECKSUM_SIZE);
+        if crc32fast::hash(data).to_be_bytes() != checksum {
+            return Err(StateError::Checksum);
+        }
+
+        let mut header = Reader { bytes: &data[4..] };
+        let version = header.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let found = String::from_utf8_lossy(header.bytes(40)?).into_owned();
+        if found != rom_hash {
+            return Err(StateError::WrongRom {
+                expected: rom_hash.to_string(),
+                found,
+            });
+        }
+        let len = header.u32()? as usize;
+        if header.bytes.len() != len {
+            return Err(StateError::Corrupt);
+        }
+
+        Ok((version, header))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Corrupt);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+
+        Ok(array)
+    }
+
+    /// Fails unless the whole payload was read.
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Corrupt),
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use crate::config::{Config, Keymap};
+use crate::options::Options;
+
+use chip8::cpu::FRAME_RATE;
+use chip8::debugger::{Command, Debugger};
+use chip8::profiler::Profiler;
+use chip8::rom::Rom;
+use chip8::{Cpu, Framebuffer};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, queue, style, terminal};
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Debugger output lines kept under the screen.
+const OUTPUT_LINES: usize = 12;
+
+/// How pixels are packed into character cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyphs {
+    /// `▀`, `▄` and `█`: 1x2 pixels per cell, 64x32 in 64x16 cells.
+    HalfBlocks,
+    /// Braille patterns: 2x4 pixels per cell, 64x32 in 32x8 cells.
+    Braille,
+}
+
+/// Draws the framebuffer as lines of text, with any lit plane counting as
+/// lit.
+pub fn render(pixels: &Framebuffer, glyphs: Glyphs) -> Vec<String> {
+    let (cell_width, cell_height) = match glyphs {
+        Glyphs::HalfBlocks => (1, 2),
+        Glyphs::Braille => (2, 4),
+    };
+    let lit =
+        |x: usize, y: usize| x < pixels.width() && y < pixels.height() && pixels.get(x, y) != 0;
+
+    (0..pixels.height())
+        .step_by(cell_height)
+        .map(|top| {
+            (0..pixels.width())
+                .step_by(cell_width)
+                .map(|left| match glyphs {
+                    Glyphs::HalfBlocks => match (lit(left, top), lit(left, top + 1)) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    },
+                    Glyphs::Braille => {
+                        // dot numbering runs down the left column, then the
+                        // right, with the bottom row added last
+                        const DOTS: [(usize, usize); 8] = [
+                            (0, 0),
+                            (0, 1),
+                            (0, 2),
+                            (1, 0),
+                            (1, 1),
+                            (1, 2),
+                            (0, 3),
+                            (1, 3),
+                        ];
+                        let bits = DOTS
+                            .iter()
+                            .enumerate()
+                            .filter(|&(_, &(x, y))| lit(left + x, top + y))
+                            .map(|(bit, _)| 1 << bit)
+                            .sum::<u32>();
+                        std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The SDL name of a terminal key, to look it up in a [`Keymap`].
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(' ') => "Space",
+        KeyCode::Char(c) => return Some(c.to_ascii_uppercase().to_string()),
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Enter => "Return",
+        KeyCode::Tab => "Tab",
+        _ => return None,
+    };
+
+    Some(name.to_string())
+}
+
+/// Most terminals only report presses, repeating them while a key is held,
+/// so a key counts as held until `timeout` passes without another press.
+/// Without a timeout keys are held until released.
+#[derive(Debug, Clone)]
+pub struct HeldKeys {
+    timeout: Option<Duration>,
+    pressed: [Option<Instant>; 16],
+}
+
+impl HeldKeys {
+    pub fn init(timeout: Option<Duration>) -> HeldKeys {
+        HeldKeys {
+            timeout,
+            pressed: [None; 16],
+        }
+    }
+
+    pub fn press(&mut self, key: usize, now: Instant) {
+        self.pressed[key] = Some(now);
+    }
+
+    pub fn release(&mut self, key: usize) {
+        self.pressed[key] = None;
+    }
+
+    pub fn keys(&self, now: Instant) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (held, pressed) in keys.iter_mut().zip(&self.pressed) {
+            *held = match self.timeout {
+                Some(timeout) => {
+                    pressed.is_some_and(|pressed| now.duration_since(pressed) < timeout)
+                }
+                None => pressed.is_some(),
+            };
+        }
+
+        keys
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen and restores it
+/// when dropped, even on a panic.
+struct RawTerminal {
+    // whether the terminal reports key releases
+    releases: bool,
+}
+
+impl RawTerminal {
+    fn init() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            queue!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        out.flush()?;
+
+        Ok(RawTerminal { releases })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.releases {
+            queue!(out, PopKeyboardEnhancementFlags).ok();
+        }
+        queue!(out, cursor::Show, terminal::LeaveAlternateScreen).ok();
+        out.flush().ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// The terminal frontend. Esc quits; under `--debug`, `:` opens a command
+/// line for debugger commands.
+pub struct Tui {
+    cpu: Cpu,
+    ipf: u32,
+    glyphs: Glyphs,
+    keymap: Keymap,
+    config: Config,
+    // command line bindings, applied over the config's keymap for the rom
+    bindings: Keymap,
+    seed: Option<u64>,
+    held: HeldKeys,
+    debugger: Option<Debugger>,
+    // profile being counted and the path it is written to
+    profile: Option<(Profiler, String)>,
+    // the command being typed, while the command line is open
+    command: Option<String>,
+    output: Vec<String>,
+}
+
+impl Tui {
+    pub fn init(options: &Options, config: Config) -> Tui {
+        Tui {
+            cpu: Cpu::init(options.quirks),
+            ipf: options.ipf,
+            glyphs: if options.braille {
+                Glyphs::Braille
+            } else {
+                Glyphs::HalfBlocks
+            },
+            keymap: Keymap::default(),
+            config,
+            bindings: options.bindings.clone(),
+            seed: options.seed,
+            held: HeldKeys::init(Some(options.key_timeout)),
+            debugger: if options.debug {
+                Some(Debugger::init())
+            } else {
+                None
+            },
+            profile: options
+                .profile
+                .as_ref()
+                .map(|path| (Profiler::init(), path.clone())),
+            command: None,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self, rom: &Rom) -> Result<(), String> {
+        self.cpu.load_rom(rom).map_err(|e| e.to_string())?;
+        if let Some(seed) = self.seed {
+            self.cpu.reseed(seed);
+        }
+
+        self.keymap = self.config.keymap(self.cpu.rom_hash());
+        self.keymap.overlay(&self.bindings);
+
+        Ok(())
+    }
+
+    /// Runs until Esc, `quit` or the program exits.
+    pub fn start(&mut self) -> Result<(), String> {
+        let result = self.run();
+        if let Some((profiler, path)) = &self.profile {
+            crate::save_profile(profiler, &self.cpu, path)?;
+        }
+
+        result
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        let terminal = RawTerminal::init().map_err(|e| e.to_string())?;
+        if terminal.releases {
+            self.held = HeldKeys::init(None);
+        }
+        if self.debugger.is_some() {
+            self.print(Debugger::status(&self.cpu));
+        }
+        let frame = Duration::from_secs(1) / FRAME_RATE;
+        let mut next_frame = Instant::now();
+        let mut draw = true;
+
+        loop {
+            while event::poll(next_frame.saturating_duration_since(Instant::now()))
+                .map_err(|e| e.to_string())?
+            {
+                if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+                    if self.key(key) {
+                        return Ok(());
+                    }
+                    draw = true;
+                }
+            }
+            next_frame += frame;
+
+            self.cpu.keys = self.held.keys(Instant::now());
+            match &mut self.debugger {
+                Some(debugger) => {
+                    if let Some(stop) = debugger.run_frame(&mut self.cpu, self.ipf) {
+                        self.print(format!("{}\n{}", stop, Debugger::status(&self.cpu)));
+                    }
+                }
+                None => match &mut self.profile {
+                    Some((profiler, _)) => profiler.run_frame(&mut self.cpu, self.ipf),
+                    None => self.cpu.run_frame(self.ipf),
+                }
+                .map_err(|e| e.to_string())?,
+            }
+            if self.cpu.should_draw || draw {
+                self.draw().map_err(|e| e.to_string())?;
+                self.cpu.should_draw = false;
+                draw = false;
+            }
+            if self.cpu.exited() && self.debugger.is_none() {
+                return Ok(());
+            }
+
+            // events are polled until the next frame is due
+            let now = Instant::now();
+            if next_frame < now {
+                // too far behind to catch up
+                next_frame = now;
+            }
+        }
+    }
+
+    /// Handles a key event, returning whether to quit.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return true;
+        }
+        if key.kind == KeyEventKind::Release {
+            if let Some(pressed) = self.keypad(key.code) {
+                self.held.release(pressed);
+            }
+            return false;
+        }
+
+        if let Some(line) = &mut self.command {
+            match key.code {
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Esc => self.command = None,
+                KeyCode::Enter => {
+                    let line = self.command.take().unwrap_or_default();
+                    return self.run_command(&line);
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Esc => return true,
+            KeyCode::Char(':') if self.debugger.is_some() => self.command = Some(String::new()),
+            code => {
+                if let Some(pressed) = self.keypad(code) {
+                    self.held.press(pressed, Instant::now());
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The CHIP-8 key bound to a terminal key.
+    fn keypad(&self, code: KeyCode) -> Option<usize> {
+        let name = key_name(code)?;
+        self.keymap
+            .host_keys()
+            .find(|(host, _)| host.eq_ignore_ascii_case(&name))
+            .map(|(_, key)| key as usize)
+    }
+
+    /// Runs a debugger command, returning whether it was `quit`.
+    fn run_command(&mut self, line: &str) -> bool {
+        let debugger = match &mut self.debugger {
+            Some(debugger) => debugger,
+            None => return false,
+        };
+        let output = match Command::parse(line) {
+            Ok(Command::Quit) => return true,
+            Ok(command) => match debugger.execute(&mut self.cpu, command) {
+                Ok(output) => output,
+                Err(error) => error,
+            },
+            Err(error) => error,
+        };
+        self.print(format!("(chip8) {}", line));
+        if !output.is_empty() {
+            self.print(output);
+        }
+
+        false
+    }
+
+    fn print(&mut self, text: String) {
+        self.output.extend(text.lines().map(str::to_string));
+        let excess = self.output.len().saturating_sub(OUTPUT_LINES);
+        self.output.drain(..excess);
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let mut out = io::stdout();
+        queue!(out, cursor::MoveTo(0, 0))?;
+        let lines = render(&self.cpu.pixels, self.glyphs);
+        let width = lines.first().map_or(0, |line| line.chars().count());
+        for line in &lines {
+            queue!(out, style::Print(line), style::Print("\r\n"))?;
+        }
+        queue!(out, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+
+        if self.debugger.is_some() {
+            queue!(out, style::Print("-".repeat(width)), style::Print("\r\n"))?;
+            for line in &self.output {
+                queue!(out, style::Print(line), style::Print("\r\n"))?;
+            }
+            match &self.command {
+                Some(line) => queue!(out, style::Print(format!(":{}", line)))?,
+                None => queue!(out, style::Print(": for a debugger command, Esc quits"))?,
+            }
+        }
+
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels() -> Framebuffer {
+        let mut pixels = Framebuffer::init(4, 4);
+        pixels.set(0, 0, 1);
+        pixels.set(1, 1, 1);
+        pixels.set(2, 0, 1);
+        pixels.set(2, 1, 2);
+        pixels.set(3, 3, 1);
+
+        pixels
+    }
+
+    #[test]
+    fn test_render_half_blocks() {
+        assert_eq!(render(&pixels(), Glyphs::HalfBlocks), ["▀▄█ ", "   ▄"]);
+    }
+
+    #[test]
+    fn test_render_braille() {
+        // dots 1 and 5, then 1, 2 and 8
+        assert_eq!(render(&pixels(), Glyphs::Braille), ["\u{2811}\u{2883}"]);
+        assert_eq!(render(&Framebuffer::init(64, 32), Glyphs::Braille).len(), 8);
+    }
+
+    #[test]
+    fn test_held_keys_time_out() {
+        let start = Instant::now();
+        let mut held = HeldKeys::init(Some(Duration::from_millis(100)));
+        held.press(5, start);
+
+        assert!(held.keys(start + Duration::from_millis(50))[5]);
+        held.press(5, start + Duration::from_millis(80));
+        assert!(held.keys(start + Duration::from_millis(150))[5]);
+        assert!(!held.keys(start + Duration::from_millis(180))[5]);
+
+        held.press(6, start);
+        held.release(6);
+        assert!(!held.keys(start)[6]);
+    }
+
+    #[test]
+    fn test_held_keys_until_released() {
+        let start = Instant::now();
+        let mut held = HeldKeys::init(None);
+        held.press(5, start);
+
+        assert!(held.keys(start + Duration::from_secs(10))[5]);
+        held.release(5);
+        assert!(!held.keys(start)[5]);
+    }
+
+    #[test]
+    fn test_key_name() {
+        assert_eq!(key_name(KeyCode::Char('q')), Some("Q".to_string()));
+        assert_eq!(key_name(KeyCode::Char(' ')), Some("Space".to_string()));
+        assert_eq!(key_name(KeyCode::Up), Some("Up".to_string()));
+        assert_eq!(key_name(KeyCode::F(1)), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Runs every ROM in `roms/` headless and compares the final screen with a
+//! golden image in `tests/golden/<rom>.pbm`.
+//!
+//! A ROM runs for [`FRAMES`] frames with the quirks the ROM database gives
+//! it, or the VIP quirks the command line defaults to, holding the keys of
+//! `tests/golden/<rom>.keys` if there is one. Run with `CHIP8_BLESS=1` to
+//! write the golden images of new or changed ROMs.
+
+use chip8::database::Database;
+use chip8::framebuffer::Framebuffer;
+use chip8::headless::{self, Headless, KeyScript};
+use chip8::rom::Rom;
+use chip8::{Cpu, Quirks};
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FRAMES: u32 = 120;
+const IPF: u32 = 10;
+const BLESS: &str = "CHIP8_BLESS";
+
+/// A screen as lit and unlit pixels.
+#[derive(Debug, PartialEq)]
+struct Image {
+    width: usize,
+    height: usize,
+    lit: Vec<bool>,
+}
+
+impl Image {
+    fn from_pixels(pixels: &Framebuffer) -> Image {
+        Image {
+            width: pixels.width(),
+            height: pixels.height(),
+            lit: pixels.rows().flatten().map(|&pixel| pixel != 0).collect(),
+        }
+    }
+
+    /// Parses a plain PBM as written by [`headless::write_pbm`].
+    fn parse_pbm(text: &str) -> Result<Image, String> {
+        let mut words = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace);
+        if words.next() != Some("P1") {
+            return Err("not a plain PBM".to_string());
+        }
+        let mut size = || {
+            words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(|| "invalid size".to_string())
+        };
+        let (width, height) = (size()?, size()?);
+        let lit = words
+            .flat_map(str::chars)
+            .map(|bit| match bit {
+                '0' => Ok(false),
+                '1' => Ok(true),
+                _ => Err(format!("invalid pixel {}", bit)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if lit.len() != width * height {
+            return Err(format!(
+                "{} pixels for a {}x{} image",
+                lit.len(),
+                width,
+                height
+            ));
+        }
+
+        Ok(Image { width, height, lit })
+    }
+
+    /// Each pixel as `#` if lit in both images, `.` if in neither, `+` if
+    /// only lit in `actual` and `-` if only lit in `self`.
+    fn diff(&self, actual: &Image) -> String {
+        if (self.width, self.height) != (actual.width, actual.height) {
+            return format!(
+                "expected {}x{}, got {}x{}\n",
+                self.width, self.height, actual.width, actual.height
+            );
+        }
+        let mut diff = String::new();
+        for (expected, actual) in self
+            .lit
+            .chunks(self.width)
+            .zip(actual.lit.chunks(self.width))
+        {
+            for (&expected, &actual) in expected.iter().zip(actual) {
+                diff.push(match (expected, actual) {
+                    (true, true) => '#',
+                    (false, false) => '.',
+                    (false, true) => '+',
+                    (true, false) => '-',
+                });
+            }
+            diff.push('\n');
+        }
+
+        diff
+    }
+}
+
+fn roms() -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = fs::read_dir("roms")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ch8"))
+        .collect();
+    roms.sort();
+
+    roms
+}
+
+/// The screen after running `path` headless.
+fn run(path: &Path, database: &Database) -> Result<Framebuffer, String> {
+    let rom = Rom::read(path.to_str().unwrap()).map_err(|e| e.to_string())?;
+    let quirks = database
+        .lookup(rom.hash())
+        .and_then(|info| info.quirks)
+        .unwrap_or_else(Quirks::vip);
+    let mut cpu = Cpu::init(quirks);
+    cpu.load_rom(&rom).map_err(|e| e.to_string())?;
+    cpu.reseed(0);
+
+    let mut runner = Headless::init(FRAMES, IPF);
+    let keys = golden(path, "keys");
+    if keys.exists() {
+        let script = fs::read_to_string(&keys).map_err(|e| e.to_string())?;
+        runner.script = KeyScript::parse(&script)?;
+    }
+    runner.run(&mut cpu).map_err(|e| e.to_string())?;
+
+    Ok(cpu.pixels)
+}
+
+fn golden(rom: &Path, extension: &str) -> PathBuf {
+    Path::new("tests/golden")
+        .join(rom.file_stem().unwrap())
+        .with_extension(extension)
+}
+
+#[test]
+fn test_golden_images() {
+    let bless = env::var_os(BLESS).is_some();
+    let database = Database::bundled();
+    let roms = roms();
+    assert!(!roms.is_empty(), "no roms in roms/");
+
+    let mut failures = String::new();
+    for path in roms {
+        let pixels = match run(&path, &database) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                writeln!(failures, "{}: {}", path.display(), e).unwrap();
+                continue;
+            }
+        };
+        let image = golden(&path, "pbm");
+
+        if bless {
+            let mut pbm = Vec::new();
+            headless::write_pbm(&pixels, &mut pbm).unwrap();
+            if fs::read(&image).ok().as_ref() != Some(&pbm) {
+                fs::write(&image, pbm).unwrap();
+                eprintln!("blessed {}", image.display());
+            }
+            continue;
+        }
+
+        let expected = match fs::read_to_string(&image) {
+            Ok(text) => Image::parse_pbm(&text),
+            Err(e) => Err(format!("{}, run with {}=1 to create it", e, BLESS)),
+        };
+        match expected {
+            Ok(expected) if expected == Image::from_pixels(&pixels) => {}
+            Ok(expected) => writeln!(
+                failures,
+                "{} differs from {} (+ newly lit, - no longer lit):\n{}",
+                path.display(),
+                image.display(),
+                expected.diff(&Image::from_pixels(&pixels))
+            )
+            .unwrap(),
+            Err(e) => writeln!(failures, "{}: {}", image.display(), e).unwrap(),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures);
+}
+
+#[test]
+fn test_diff() {
+    let expected = Image::parse_pbm("P1\n3 2\n1 1 0\n0 0 0\n").unwrap();
+    let actual = Image::parse_pbm("P1\n# a comment\n3 2\n101\n000\n").unwrap();
+
+    assert_eq!(expected.diff(&actual), "#-+\n...\n");
+    assert!(Image::parse_pbm("P1\n3 2\n1 1\n").is_err());
+}
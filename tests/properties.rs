@@ -0,0 +1,145 @@
+//! Checks the decoder against an independent reference over every word, and
+//! runs random programs through the [`Cpu`] to make sure nothing panics.
+//!
+//! The fuzz targets in `fuzz/` check the same properties for longer.
+
+use chip8::instruction::Instruction::{self, *};
+use chip8::{Cpu, Quirks};
+
+use proptest::prelude::*;
+
+/// Decodes `word` one nibble at a time, the way the opcode tables are
+/// written, rather than by ranges like [`Instruction::parse`].
+fn reference(word: u16) -> Option<Instruction> {
+    let nibbles = [word >> 12, word >> 8 & 0xF, word >> 4 & 0xF, word & 0xF];
+    let (x, y, n) = (nibbles[1], nibbles[2], nibbles[3]);
+    let nnn = word & 0x0FFF;
+    let kk = word as u8;
+
+    let inst = match nibbles {
+        [0x0, 0x0, 0xE, 0x0] => CLS,
+        [0x0, 0x0, 0xE, 0xE] => RET,
+        [0x0, 0x0, 0xC, n] => SCD(n as u8),
+        [0x0, 0x0, 0xF, 0xB] => SCR,
+        [0x0, 0x0, 0xF, 0xC] => SCL,
+        [0x0, 0x0, 0xF, 0xD] => EXIT,
+        [0x0, 0x0, 0xF, 0xE] => LOW,
+        [0x0, 0x0, 0xF, 0xF] => HIGH,
+        [0x0, ..] => SYS(nnn),
+        [0x1, ..] => JP_ADDR(nnn),
+        [0x2, ..] => CALL_ADDR(nnn),
+        [0x3, ..] => SE_BYTE(x, kk),
+        [0x4, ..] => SNE_BYTE(x, kk),
+        [0x5, _, _, 0x0] => SE(x, y),
+        [0x5, _, _, 0x2] => SAVE(x, y),
+        [0x5, _, _, 0x3] => LOAD(x, y),
+        [0x6, ..] => LD_BYTE(x, kk),
+        [0x7, ..] => ADD_BYTE(x, kk),
+        [0x8, _, _, 0x0] => LD(x, y),
+        [0x8, _, _, 0x1] => OR(x, y),
+        [0x8, _, _, 0x2] => AND(x, y),
+        [0x8, _, _, 0x3] => XOR(x, y),
+        [0x8, _, _, 0x4] => ADD(x, y),
+        [0x8, _, _, 0x5] => SUB(x, y),
+        [0x8, _, _, 0x6] => SHR(x, y),
+        [0x8, _, _, 0x7] => SUBN(x, y),
+        [0x8, _, _, 0xE] => SHL(x, y),
+        [0x9, _, _, 0x0] => SNE(x, y),
+        [0xA, ..] => LD_I(nnn),
+        [0xB, ..] => JP_V0(nnn),
+        [0xC, ..] => RND_BYTE(x, kk),
+        [0xD, ..] => DRW(x, y, n as u8),
+        [0xE, _, 0x9, 0xE] => SKP(x),
+        [0xE, _, 0xA, 0x1] => SKNP(x),
+        [0xF, 0x0, 0x0, 0x0] => LD_I_LONG(0),
+        [0xF, _, 0x0, 0x1] => PLANE(x as u8),
+        [0xF, 0x0, 0x0, 0x2] => AUDIO,
+        [0xF, _, 0x0, 0x7] => LD_DT(x),
+        [0xF, _, 0x0, 0xA] => LD_KEY(x),
+        [0xF, _, 0x1, 0x5] => LD_DT_SET(x),
+        [0xF, _, 0x1, 0x8] => LD_ST_SET(x),
+        [0xF, _, 0x1, 0xE] => ADD_I(x),
+        [0xF, _, 0x2, 0x9] => LD_F(x),
+        [0xF, _, 0x3, 0x0] => LD_HF(x),
+        [0xF, _, 0x3, 0x3] => LD_B(x),
+        [0xF, _, 0x3, 0xA] => PITCH(x),
+        [0xF, _, 0x5, 0x5] => LD_STORE_I(x),
+        [0xF, _, 0x6, 0x5] => LD_READ_I(x),
+        [0xF, _, 0x7, 0x5] => LD_STORE_R(x),
+        [0xF, _, 0x8, 0x5] => LD_READ_R(x),
+        _ => return None,
+    };
+
+    Some(inst)
+}
+
+#[test]
+fn test_parse_matches_reference() {
+    for word in 0..=u16::MAX {
+        let parsed = Instruction::parse(word);
+        assert_eq!(
+            parsed.as_ref().ok(),
+            reference(word).as_ref(),
+            "{:#06x}",
+            word
+        );
+        if let Ok(inst) = parsed {
+            assert_eq!(inst.encode(), word, "{}", inst);
+        }
+    }
+}
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    any::<[bool; 5]>().prop_map(|flags| Quirks {
+        vf_reset: flags[0],
+        increment_i: flags[1],
+        shift_vx: flags[2],
+        jump_vx: flags[3],
+        clip_sprites: flags[4],
+    })
+}
+
+proptest! {
+    #[test]
+    fn test_random_programs_never_panic(
+        quirks in quirks(),
+        // start I anywhere, including right at the end of memory
+        i in any::<u16>(),
+        program in prop::collection::vec(any::<u16>(), 1..256),
+        keys in any::<[bool; 16]>(),
+        seed in any::<u64>(),
+    ) {
+        let mut rom = vec![0xF0, 0x00, (i >> 8) as u8, i as u8];
+        rom.extend(program.iter().flat_map(|word| word.to_be_bytes()));
+        let mut cpu = Cpu::init(quirks);
+        cpu.load(&rom).unwrap();
+        cpu.reseed(seed);
+        cpu.keys = keys;
+
+        for _ in 0..1000 {
+            let pc = cpu.pc();
+            if cpu.cycle().is_err() {
+                prop_assert_eq!(cpu.pc(), pc);
+                break;
+            }
+            cpu.tick_timers();
+        }
+    }
+
+    #[test]
+    fn test_execute_never_panics(
+        quirks in quirks(),
+        setup in prop::collection::vec(any::<u16>(), 0..32),
+        word in any::<u16>(),
+    ) {
+        let mut cpu = Cpu::init(quirks);
+        for word in setup {
+            if let Ok(inst) = Instruction::parse(word) {
+                let _ = cpu.execute(inst);
+            }
+        }
+        if let Ok(inst) = Instruction::parse(word) {
+            let _ = cpu.execute(inst);
+        }
+    }
+}